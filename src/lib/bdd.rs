@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use super::{netlist::gate_value, node::CalcNode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct BddNode {
+    var: usize,
+    low: usize,
+    high: usize,
+}
+
+/// Reduced ordered binary decision diagram over a fixed variable order. Nodes are referred to
+/// by index, `Bdd::FALSE` and `Bdd::TRUE` are the two terminals.
+#[derive(Debug, Clone)]
+pub struct Bdd {
+    pub vars: Vec<String>,
+    nodes: Vec<BddNode>,
+    unique: HashMap<BddNode, usize>,
+}
+
+impl Bdd {
+    pub const FALSE: usize = 0;
+    pub const TRUE: usize = 1;
+
+    pub fn new(vars: Vec<String>) -> Bdd {
+        let terminal = |x| BddNode {
+            var: usize::MAX,
+            low: x,
+            high: x,
+        };
+        Bdd {
+            vars,
+            nodes: vec![terminal(Bdd::FALSE), terminal(Bdd::TRUE)],
+            unique: HashMap::new(),
+        }
+    }
+
+    fn make(&mut self, var: usize, low: usize, high: usize) -> usize {
+        if low == high {
+            return low;
+        }
+        let node = BddNode { var, low, high };
+        if let Some(x) = self.unique.get(&node) {
+            return *x;
+        }
+        self.nodes.push(node);
+        self.unique.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    pub fn var(&mut self, name: &str) -> Result<usize, String> {
+        let i = self
+            .vars
+            .iter()
+            .position(|f| f == name)
+            .ok_or_else(|| format!("Unknown input {}", name))?;
+        Ok(self.make(i, Bdd::FALSE, Bdd::TRUE))
+    }
+
+    pub fn apply(&mut self, op: &dyn Fn(bool, bool) -> bool, u: usize, v: usize) -> usize {
+        fn go(
+            bdd: &mut Bdd,
+            op: &dyn Fn(bool, bool) -> bool,
+            u: usize,
+            v: usize,
+            memo: &mut HashMap<(usize, usize), usize>,
+        ) -> usize {
+            if u <= Bdd::TRUE && v <= Bdd::TRUE {
+                return op(u == Bdd::TRUE, v == Bdd::TRUE) as usize;
+            }
+            if let Some(x) = memo.get(&(u, v)) {
+                return *x;
+            }
+            let (nu, nv) = (bdd.nodes[u], bdd.nodes[v]);
+            let var = nu.var.min(nv.var);
+            let (ul, uh) = if nu.var == var {
+                (nu.low, nu.high)
+            } else {
+                (u, u)
+            };
+            let (vl, vh) = if nv.var == var {
+                (nv.low, nv.high)
+            } else {
+                (v, v)
+            };
+            let low = go(bdd, op, ul, vl, memo);
+            let high = go(bdd, op, uh, vh, memo);
+            let ans = bdd.make(var, low, high);
+            memo.insert((u, v), ans);
+            ans
+        }
+        go(self, op, u, v, &mut HashMap::new())
+    }
+
+    /// Builds the diagram of a gate tree, with gates following `netlist::gate_value`.
+    pub fn build(&mut self, node: &CalcNode) -> Result<usize, String> {
+        match node {
+            CalcNode::Text(x) => self.var(x),
+            CalcNode::SingleValue(x) => Ok(((*x as i64) & 1) as usize),
+            CalcNode::Function(x) => {
+                let ins = x
+                    .params
+                    .iter()
+                    .map(|f| self.build(f))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let kind = x.name.as_str();
+                let (base, negate) = match kind {
+                    "nand" => ("and", true),
+                    "nor" => ("or", true),
+                    "xnor" => ("xor", true),
+                    _ => (kind, false),
+                };
+                let gate = |kind: &str, ins: &[bool]| -> Result<bool, String> {
+                    let ins: Vec<u64> = ins.iter().map(|f| *f as u64).collect();
+                    Ok(gate_value(kind, &ins)? & 1 == 1)
+                };
+                let ans = match (base, ins.as_slice()) {
+                    ("and" | "or" | "xor", [first, rest @ ..]) if !rest.is_empty() => {
+                        let mut ans = *first;
+                        for f in rest {
+                            ans = self.apply(&|x, y| gate(base, &[x, y]).unwrap(), ans, *f);
+                        }
+                        if negate {
+                            ans = self.apply(&|x, _| !x, ans, ans);
+                        }
+                        ans
+                    }
+                    (_, [u]) => {
+                        gate(kind, &[false])?;
+                        self.apply(&|x, _| gate(kind, &[x]).unwrap(), *u, *u)
+                    }
+                    (_, [u, v, ..]) => {
+                        gate(kind, &[false, false])?;
+                        self.apply(&|x, y| gate(kind, &[x, y]).unwrap(), *u, *v)
+                    }
+                    _ => return Err(format!("{} has no inputs", kind)),
+                };
+                Ok(ans)
+            }
+            x => Err(format!("{:#} is not a boolean expression", x)),
+        }
+    }
+
+    /// An assignment of every variable that takes `u` to `Bdd::TRUE`, if there is one.
+    /// Variables the diagram doesn't test are set to false.
+    pub fn satisfy(&self, mut u: usize) -> Option<Vec<bool>> {
+        if u == Bdd::FALSE {
+            return None;
+        }
+        let mut ans = vec![false; self.vars.len()];
+        while u != Bdd::TRUE {
+            let node = self.nodes[u];
+            if node.low != Bdd::FALSE {
+                u = node.low;
+            } else {
+                ans[node.var] = true;
+                u = node.high;
+            }
+        }
+        Some(ans)
+    }
+}
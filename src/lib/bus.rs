@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::{
+    funcs::is_relation,
+    node::{CalcFunctionData, CalcNode},
+};
+
+/// Bit range `[msb:lsb]` of a bus. Ranges are descending, as in `a[7:0]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusRange {
+    pub msb: usize,
+    pub lsb: usize,
+}
+
+impl BusRange {
+    pub fn width(&self) -> usize {
+        self.msb - self.lsb + 1
+    }
+
+    /// Bit indices from the least significant up.
+    pub fn bits(&self) -> std::ops::RangeInclusive<usize> {
+        self.lsb..=self.msb
+    }
+
+    pub fn contains(&self, other: &BusRange) -> bool {
+        self.lsb <= other.lsb && other.msb <= self.msb
+    }
+}
+
+impl fmt::Display for BusRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}:{}]", self.msb, self.lsb)
+    }
+}
+
+/// Splits a bit-select `a[3]` or part-select `a[7:4]` into the bus name and the range.
+pub fn parse_select(text: &str) -> Option<(&str, BusRange)> {
+    let (name, range) = text.strip_suffix(']')?.split_once('[')?;
+    let (msb, lsb) = range.split_once(':').unwrap_or((range, range));
+    let (msb, lsb) = (msb.trim().parse().ok()?, lsb.trim().parse().ok()?);
+    if name.is_empty() || msb < lsb {
+        return None;
+    }
+    Some((name, BusRange { msb, lsb }))
+}
+
+/// Name of one bit of a bus, which is also how the bit is written in Verilog.
+pub fn bit_name(name: &str, bit: usize) -> String {
+    format!("{}[{}]", name, bit)
+}
+
+/// Bus name and bit index of a one bit signal name, `None` for a plain wire.
+pub fn split_bit(name: &str) -> (&str, Option<usize>) {
+    match parse_select(name) {
+        Some((base, r)) if r.width() == 1 => (base, Some(r.lsb)),
+        _ => (name, None),
+    }
+}
+
+/// Gathers one bit signal names into buses, in order of first appearance. Plain wires have no
+/// range, the range of a bus spans the bits named.
+pub fn group_bits<'a>(names: &[&'a str]) -> Vec<(&'a str, Option<BusRange>)> {
+    let mut groups: Vec<(&str, Option<BusRange>)> = Vec::new();
+    for name in names {
+        let (base, bit) = split_bit(name);
+        match (groups.iter_mut().find(|f| f.0 == base), bit) {
+            (Some((_, Some(r))), Some(bit)) => {
+                r.msb = r.msb.max(bit);
+                r.lsb = r.lsb.min(bit);
+            }
+            (Some(_), _) => {}
+            (None, bit) => groups.push((base, bit.map(|f| BusRange { msb: f, lsb: f }))),
+        }
+    }
+    groups
+}
+
+/// Expands gate trees over buses into one tree per bit, least significant bit first. Sizing
+/// follows Verilog: an expression is as wide as its widest operand, or its context if that is
+/// wider, and narrower operands are zero-extended to that width before any gate sees them. So
+/// `s & a` only keeps bit 0 of `a`, and `~s` in a four bit expression is 1 in its upper bits.
+/// Shifts and rotates by a constant rewire the bits. `concat` joins its operands with the first
+/// one as the most significant part.
+pub struct Blaster<'a> {
+    buses: &'a [(String, BusRange)],
+    counter: Arc<AtomicUsize>,
+    memo: HashMap<(usize, usize), Vec<CalcNode>>,
+    widths: HashMap<usize, usize>,
+}
+
+impl<'a> Blaster<'a> {
+    pub fn new(buses: &'a [(String, BusRange)], counter: Arc<AtomicUsize>) -> Blaster<'a> {
+        Blaster {
+            buses,
+            counter,
+            memo: HashMap::new(),
+            widths: HashMap::new(),
+        }
+    }
+
+    fn declared(&self, name: &str) -> Option<BusRange> {
+        self.buses.iter().find(|f| f.0 == name).map(|f| f.1)
+    }
+
+    /// Whether `node` refers to a bus at all. Trees that don't are emitted as they are.
+    pub fn is_needed(&self, node: &CalcNode) -> bool {
+        match node {
+            CalcNode::Text(x) => self.declared(x).is_some() || parse_select(x).is_some(),
+            CalcNode::Function(x) => {
+                x.name == "concat" || x.params.iter().any(|f| self.is_needed(f))
+            }
+            _ => false,
+        }
+    }
+
+    /// Bits of `node` on its own, before any context widens it.
+    pub fn width(&mut self, node: &CalcNode) -> Result<usize, String> {
+        match node {
+            CalcNode::Text(x) => Ok(self.blast_text(x)?.len()),
+            CalcNode::SingleValue(x) => Ok(constant_bits(*x).len()),
+            CalcNode::Function(x) => {
+                if let Some(ans) = self.widths.get(&x.id) {
+                    return Ok(*ans);
+                }
+                let widths = x
+                    .params
+                    .iter()
+                    .map(|f| self.width(f))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let ans = match x.name.as_str() {
+                    "concat" => widths.iter().sum(),
+                    _ if x.operator.is_some_and(is_relation) => 1,
+                    _ if is_shift(&x.name) => widths[0],
+                    _ => widths.iter().copied().max().unwrap_or(1),
+                };
+                self.widths.insert(x.id, ans);
+                Ok(ans)
+            }
+            x => Err(format!("{:#} can't be turned into gates", x)),
+        }
+    }
+
+    /// Bits of `node` in an expression `width` bits wide, never fewer than `width`.
+    pub fn blast(&mut self, node: &CalcNode, width: usize) -> Result<Vec<CalcNode>, String> {
+        let mut bits = match node {
+            CalcNode::Text(x) => self.blast_text(x)?,
+            CalcNode::SingleValue(x) => constant_bits(*x),
+            CalcNode::Function(x) => {
+                if let Some(ans) = self.memo.get(&(x.id, width)) {
+                    return Ok(ans.clone());
+                }
+                let ans = self.blast_function(x, width)?;
+                self.memo.insert((x.id, width), ans.clone());
+                ans
+            }
+            x => return Err(format!("{:#} can't be turned into gates", x)),
+        };
+        if bits.len() < width {
+            bits.resize(width, CalcNode::SingleValue(0.0));
+        }
+        Ok(bits)
+    }
+
+    fn blast_text(&self, x: &str) -> Result<Vec<CalcNode>, String> {
+        let bits = |name: &str, r: BusRange| {
+            r.bits()
+                .map(|i| CalcNode::Text(bit_name(name, i)))
+                .collect()
+        };
+        if let Some(r) = self.declared(x) {
+            return Ok(bits(x, r));
+        }
+        match parse_select(x) {
+            Some((name, r)) => match self.declared(name) {
+                Some(d) if !d.contains(&r) => Err(format!("{} is outside {}{}", x, name, d)),
+                _ => Ok(bits(name, r)),
+            },
+            None => Ok(vec![CalcNode::Text(x.to_string())]),
+        }
+    }
+
+    fn blast_function(
+        &mut self,
+        x: &CalcFunctionData,
+        width: usize,
+    ) -> Result<Vec<CalcNode>, String> {
+        //the operands of a concatenation or a relation keep their own width
+        let own = |this: &mut Self, f: &CalcNode| {
+            let width = this.width(f)?;
+            this.blast(f, width)
+        };
+        if x.name == "concat" {
+            let params = x
+                .params
+                .iter()
+                .map(|f| own(self, f))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(params.into_iter().rev().flatten().collect());
+        }
+        let mut width = width;
+        if let Some(op) = x.operator.filter(|f| is_relation(*f)) {
+            if x.params
+                .iter()
+                .any(|f| own(self, f).is_ok_and(|f| f.len() > 1))
+            {
+                return Err(format!("{} of a bus is not supported", op.symbol()));
+            }
+            width = 1;
+        }
+        if is_shift(&x.name) {
+            //rotates and arithmetic shifts depend on where the top bit of the operand is
+            if matches!(x.name.as_str(), "ashr" | "rotl" | "rotr") {
+                width = self.width(&x.params[0])?;
+            }
+            let bits = self.blast(&x.params[0], width)?;
+            if bits.len() > 1 {
+                let CalcNode::SingleValue(n) = x.params[1] else {
+                    return Err(format!("{} of a bus needs a constant amount", x.name));
+                };
+                return Ok(Self::rewire(&x.name, &bits, n as usize));
+            }
+        }
+
+        let operands = x
+            .params
+            .iter()
+            .map(|f| self.blast(f, width))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((0..width)
+            .map(|i| CalcFunctionData {
+                params: operands.iter().map(|f| f[i].clone()).collect(),
+                id: self.counter.fetch_add(1, Ordering::SeqCst),
+                ..x.clone()
+            })
+            .map(CalcNode::Function)
+            .collect())
+    }
+
+    fn rewire(kind: &str, bits: &[CalcNode], n: usize) -> Vec<CalcNode> {
+        let w = bits.len();
+        let zero = CalcNode::SingleValue(0.0);
+        (0..w)
+            .map(|i| match kind {
+                "shl" if i >= n => bits[i - n].clone(),
+                "shr" | "ashr" if i + n < w => bits[i + n].clone(),
+                "ashr" => bits[w - 1].clone(),
+                "rotl" => bits[(i + w - n % w) % w].clone(),
+                "rotr" => bits[(i + n) % w].clone(),
+                _ => zero.clone(),
+            })
+            .collect()
+    }
+}
+
+fn is_shift(name: &str) -> bool {
+    matches!(name, "shl" | "shr" | "ashr" | "rotl" | "rotr")
+}
+
+/// Bits of a constant, as many as its highest set bit needs.
+fn constant_bits(x: f64) -> Vec<CalcNode> {
+    let x = x as i64 as u64;
+    let width = (64 - x.leading_zeros() as usize).max(1);
+    (0..width)
+        .map(|i| CalcNode::SingleValue(((x >> i) & 1) as f64))
+        .collect()
+}
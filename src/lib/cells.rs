@@ -0,0 +1,608 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use super::{
+    context::{ContextManager, ContextType},
+    netlist::{gate_value, FlipFlop, Gate, Netlist, Signal},
+    node::CalcNode,
+    truth::inputs_of,
+};
+
+/// What tree covering minimizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellGoal {
+    Area,
+    Delay,
+}
+
+impl TryFrom<&str> for CellGoal {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "area" => Ok(CellGoal::Area),
+            "delay" => Ok(CellGoal::Delay),
+            _ => Err(format!("Expected area or delay, got {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for CellGoal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellGoal::Area => write!(f, "area"),
+            CellGoal::Delay => write!(f, "delay"),
+        }
+    }
+}
+
+/// One cell of a library: a single output gate computing `function` of its `pins`.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub name: String,
+    /// Input pins, in order of first use in `function`.
+    pub pins: Vec<String>,
+    pub output: String,
+    pub area: f64,
+    pub delay: f64,
+    /// The function as written in the library.
+    pub text: String,
+    /// Gate tree over the pins, as the verilog context lowers it.
+    pub function: CalcNode,
+}
+
+impl Cell {
+    /// Value of the output for the pin values `ins`.
+    pub fn evaluate(&self, ins: &[bool]) -> Result<bool, String> {
+        fn visit(cell: &Cell, node: &CalcNode, ins: &[bool]) -> Result<u64, String> {
+            match node {
+                CalcNode::Text(x) => Ok(ins[cell.pins.iter().position(|f| f == x).unwrap()] as u64),
+                CalcNode::Function(x) => {
+                    let params = x
+                        .params
+                        .iter()
+                        .map(|f| visit(cell, f, ins))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    gate_value(&x.name, &params)
+                }
+                x => Err(format!("{:#} in cell {}", x, cell.name)),
+            }
+        }
+        Ok(visit(self, &self.function, ins)? & 1 == 1)
+    }
+
+    /// Instance of the cell driving the wire of `gate`, e.g. `NAND2 u_3(.a(x), .b(w_1), .y(w_3));`.
+    pub fn instance(&self, gate: &Gate, netlist: &Netlist) -> String {
+        let connections: Vec<String> = self
+            .pins
+            .iter()
+            .zip(&gate.inputs)
+            .map(|(pin, signal)| format!(".{}({})", pin, netlist.name_of(signal)))
+            .chain(std::iter::once(format!(
+                ".{}({})",
+                self.output,
+                netlist.wire_name(gate.id)
+            )))
+            .collect();
+        format!("{} u_{}({});", self.name, gate.id, connections.join(", "))
+    }
+}
+
+/// Node of a circuit decomposed into two-input nands and inverters, the form both the cells
+/// and the netlist are brought to before they are matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Base {
+    Leaf(usize),
+    Inv(usize),
+    Nand(usize, usize),
+}
+
+/// Hash-consed DAG of `Base` nodes. Nodes come after their operands, and a double inversion
+/// is never built.
+#[derive(Debug, Clone, Default)]
+struct BaseGraph {
+    nodes: Vec<Base>,
+    index: HashMap<Base, usize>,
+}
+
+impl BaseGraph {
+    fn add(&mut self, node: Base) -> usize {
+        let node = match node {
+            Base::Nand(x, y) if y < x => Base::Nand(y, x),
+            x => x,
+        };
+        if let Some(x) = self.index.get(&node) {
+            return *x;
+        }
+        self.nodes.push(node);
+        self.index.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn leaf(&mut self, x: usize) -> usize {
+        self.add(Base::Leaf(x))
+    }
+
+    fn inv(&mut self, x: usize) -> usize {
+        match self.nodes[x] {
+            Base::Inv(y) => y,
+            _ => self.add(Base::Inv(x)),
+        }
+    }
+
+    fn nand(&mut self, x: usize, y: usize) -> usize {
+        self.add(Base::Nand(x, y))
+    }
+
+    fn and(&mut self, x: usize, y: usize) -> usize {
+        let n = self.nand(x, y);
+        self.inv(n)
+    }
+
+    fn or(&mut self, x: usize, y: usize) -> usize {
+        let (x, y) = (self.inv(x), self.inv(y));
+        self.nand(x, y)
+    }
+
+    fn xor(&mut self, x: usize, y: usize) -> usize {
+        let (nx, ny) = (self.inv(x), self.inv(y));
+        let (l, r) = (self.nand(x, ny), self.nand(nx, y));
+        self.nand(l, r)
+    }
+
+    /// Decomposes a gate of kind `kind` over the nodes `ins`.
+    fn gate(&mut self, kind: &str, ins: &[usize]) -> Result<usize, String> {
+        let fold = |graph: &mut BaseGraph, f: fn(&mut BaseGraph, usize, usize) -> usize| {
+            ins[1..].iter().fold(ins[0], |x, y| f(graph, x, *y))
+        };
+        let ans = match kind {
+            "not" => self.inv(ins[0]),
+            "buf" => ins[0],
+            "and" => fold(self, Self::and),
+            "or" => fold(self, Self::or),
+            "xor" => fold(self, Self::xor),
+            "nand" => {
+                let x = fold(self, Self::and);
+                self.inv(x)
+            }
+            "nor" => {
+                let x = fold(self, Self::or);
+                self.inv(x)
+            }
+            "xnor" => {
+                let x = fold(self, Self::xor);
+                self.inv(x)
+            }
+            _ => return Err(format!("{} gates can't be mapped to cells", kind)),
+        };
+        Ok(ans)
+    }
+}
+
+/// Cells read from a library file, one per line as `NAME AREA DELAY out = function`, e.g.
+/// `AOI21 3 1.4 y = ~((a & b) | c)`. The function is written like any verilog context
+/// expression, its inputs are the pins. `#` starts a comment.
+#[derive(Debug, Clone, Default)]
+pub struct CellLibrary {
+    pub cells: Vec<Cell>,
+    patterns: Vec<(BaseGraph, usize)>,
+}
+
+impl CellLibrary {
+    pub fn load(path: &str) -> Result<CellLibrary, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        CellLibrary::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<CellLibrary, String> {
+        let mut library = CellLibrary::default();
+        let mut ctx = ContextManager::new();
+        ctx.push_stack_frame(ContextType::Verilog);
+        for (row, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            Self::parse_cell(line, &mut ctx)
+                .and_then(|f| library.add(f))
+                .map_err(|e| format!("Line {} of the cell library: {}", row + 1, e))?;
+        }
+        if library.cells.is_empty() {
+            return Err("The cell library has no cells".to_string());
+        }
+        Ok(library)
+    }
+
+    fn parse_cell(line: &str, ctx: &mut ContextManager) -> Result<Cell, String> {
+        let mut fields = Vec::new();
+        let mut rest = line;
+        for _ in 0..3 {
+            let (field, tail) = rest
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("Expected NAME AREA DELAY out = function, got {}", line))?;
+            fields.push(field);
+            rest = tail.trim_start();
+        }
+        let (name, area, delay) = (fields[0], fields[1], fields[2]);
+        let number = |x: &str| {
+            x.parse::<f64>()
+                .map_err(|_| format!("Expected a number for {}, got {}", name, x))
+        };
+        let (output, text) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("Expected out = function for {}", name))?;
+        let text = text.trim();
+        let tree = crate::parse(text).ok_or_else(|| format!("Expected a function for {}", name))?;
+        let function = ctx.eval(&tree);
+        let pins = inputs_of(&function);
+        if pins.is_empty() {
+            return Err(format!("{} has no inputs", name));
+        }
+        Ok(Cell {
+            name: name.to_string(),
+            pins,
+            output: output.trim().to_string(),
+            area: number(area)?,
+            delay: number(delay)?,
+            text: text.to_string(),
+            function,
+        })
+    }
+
+    fn add(&mut self, cell: Cell) -> Result<(), String> {
+        fn visit(cell: &Cell, node: &CalcNode, graph: &mut BaseGraph) -> Result<usize, String> {
+            match node {
+                CalcNode::Text(x) => Ok(graph.leaf(cell.pins.iter().position(|f| f == x).unwrap())),
+                CalcNode::Function(x) => {
+                    let ins = x
+                        .params
+                        .iter()
+                        .map(|f| visit(cell, f, graph))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    graph.gate(&x.name, &ins)
+                }
+                x => Err(format!("{:#} can't be part of cell {}", x, cell.name)),
+            }
+        }
+        let mut graph = BaseGraph::default();
+        let root = visit(&cell, &cell.function, &mut graph)?;
+        self.patterns.push((graph, root));
+        self.cells.push(cell);
+        Ok(())
+    }
+}
+
+impl fmt::Display for CellLibrary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .cells
+            .iter()
+            .map(|c| {
+                format!(
+                    "{} {} {} {} = {}",
+                    c.name, c.area, c.delay, c.output, c.text
+                )
+            })
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// A subject node in one polarity, `true` for its complement. Nodes are never inverters, an
+/// inverter is its operand in the other polarity.
+type Literal = (usize, bool);
+
+/// Cell chosen to drive a literal, with the literals its pins connect to.
+#[derive(Debug, Clone)]
+struct Choice {
+    cell: usize,
+    binds: Vec<Literal>,
+    area: f64,
+    arrival: f64,
+}
+
+/// Tree covering of a netlist with the cells of a library. Both the netlist and the cells are
+/// decomposed into nands and inverters, the netlist is split into trees at every node read
+/// more than once, and every node is covered in both polarities so a cell with an inverted
+/// output can stand in for a nand followed by an inverter.
+pub struct Mapper<'a> {
+    library: &'a CellLibrary,
+    goal: CellGoal,
+    subject: BaseGraph,
+    leaves: Vec<Signal>,
+    fanout: Vec<usize>,
+    best: Vec<[Option<Choice>; 2]>,
+}
+
+impl<'a> Mapper<'a> {
+    pub fn new(library: &'a CellLibrary, goal: CellGoal) -> Mapper<'a> {
+        Mapper {
+            library,
+            goal,
+            subject: BaseGraph::default(),
+            leaves: Vec::new(),
+            fanout: Vec::new(),
+            best: Vec::new(),
+        }
+    }
+
+    /// Netlist of cell instances computing the outputs of `netlist`, with the least area or
+    /// the earliest arrival, the other breaking ties.
+    pub fn map(mut self, netlist: &Netlist) -> Result<Netlist, String> {
+        let mut wires = HashMap::new();
+        for gate in &netlist.gates {
+            let ins: Vec<usize> = gate
+                .inputs
+                .iter()
+                .map(|f| self.node_of(f, &wires))
+                .collect();
+            wires.insert(gate.id, self.subject.gate(&gate.kind, &ins)?);
+        }
+        let outputs: Vec<usize> = netlist.sinks().map(|f| self.node_of(f, &wires)).collect();
+
+        self.fanout = vec![0; self.subject.nodes.len()];
+        let mut seen = vec![false; self.subject.nodes.len()];
+        let mut pending = outputs.clone();
+        for x in &outputs {
+            self.fanout[*x] += 1;
+        }
+        while let Some(x) = pending.pop() {
+            if std::mem::replace(&mut seen[x], true) {
+                continue;
+            }
+            for y in self.operands(x) {
+                self.fanout[y] += 1;
+                pending.push(y);
+            }
+        }
+
+        for x in 0..self.subject.nodes.len() {
+            let mut best = [None, None];
+            if !matches!(self.subject.nodes[x], Base::Inv(_)) {
+                //a literal may be covered by an inverter of the opposite one, as long as the
+                //opposite one isn't covered by an inverter of it in turn
+                let first = [self.cover(x, false, &best), self.cover(x, true, &best)];
+                let positive = self.cover(x, false, &first);
+                best[1] = match &positive {
+                    Some(c) if c.binds.contains(&(x, true)) => first[1].clone(),
+                    _ => self.cover(x, true, &[positive.clone(), None]),
+                };
+                best[0] = positive;
+            }
+            self.best.push(best);
+        }
+
+        let mut mapped = Netlist::new(&netlist.name);
+        mapped.inputs = netlist.inputs.clone();
+        mapped.notes = netlist.notes.clone();
+        mapped.references = netlist.references.clone();
+        mapped.register_references = netlist.register_references.clone();
+        mapped.prevs = netlist.prevs.clone();
+        let mut emitted = HashMap::new();
+        let (outputs, flops) = outputs.split_at(netlist.outputs.len());
+        for ((name, _), x) in netlist.outputs.iter().zip(outputs) {
+            let signal = self.emit(self.literal(*x, false), &mut emitted, &mut mapped)?;
+            mapped.outputs.push((name.to_string(), signal));
+        }
+        for (flop, x) in netlist.flops.iter().zip(flops) {
+            let d = self.emit(self.literal(*x, false), &mut emitted, &mut mapped)?;
+            mapped.flops.push(FlipFlop { d, ..flop.clone() });
+        }
+        //a cell computing the wire of a labelled gate takes its label
+        for gate in &netlist.gates {
+            let label = netlist.labels.get(&gate.id);
+            let cell = emitted.get(&self.literal(wires[&gate.id], false));
+            if let (Some(label), Some(Signal::Wire(id))) = (label, cell) {
+                mapped.labels.entry(*id).or_insert(label.to_string());
+            }
+        }
+        let used = self
+            .library
+            .cells
+            .iter()
+            .filter(|c| mapped.gates.iter().any(|f| f.kind == c.name));
+        mapped.cells = used.cloned().collect();
+
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for gate in &mapped.gates {
+            *counts.entry(&gate.kind).or_insert(0) += 1;
+        }
+        let counts: Vec<String> = counts
+            .iter()
+            .map(|(kind, n)| format!("{} {}", kind, n))
+            .collect();
+        let area: f64 = mapped
+            .gates
+            .iter()
+            .filter_map(|g| mapped.cell(&g.kind))
+            .map(|c| c.area)
+            .sum();
+        let note = format!(
+            "cells: {} ({}), area {}, delay {} (minimizing {})",
+            mapped.gates.len(),
+            counts.join(", "),
+            area,
+            Self::arrival(&mapped),
+            self.goal
+        );
+        mapped.notes.push(note);
+        Ok(mapped)
+    }
+
+    fn node_of(&mut self, signal: &Signal, wires: &HashMap<usize, usize>) -> usize {
+        if let Signal::Wire(x) = signal {
+            return wires[x];
+        }
+        let i = match self.leaves.iter().position(|f| f == signal) {
+            Some(i) => i,
+            None => {
+                self.leaves.push(signal.clone());
+                self.leaves.len() - 1
+            }
+        };
+        self.subject.leaf(i)
+    }
+
+    fn operands(&self, x: usize) -> Vec<usize> {
+        match self.subject.nodes[x] {
+            Base::Leaf(_) => vec![],
+            Base::Inv(y) => vec![y],
+            Base::Nand(y, z) => vec![y, z],
+        }
+    }
+
+    /// Node `x`, or its complement, as a literal, looking through inverters. Also tells
+    /// whether every inverter looked through is read only once.
+    fn literal_through(&self, mut x: usize, mut inverted: bool) -> (Literal, bool) {
+        let mut exclusive = true;
+        while let Base::Inv(y) = self.subject.nodes[x] {
+            exclusive &= self.fanout[x] == 1;
+            x = y;
+            inverted = !inverted;
+        }
+        ((x, inverted), exclusive)
+    }
+
+    fn literal(&self, x: usize, inverted: bool) -> Literal {
+        self.literal_through(x, inverted).0
+    }
+
+    /// Cheapest cell, and its connections, to drive literal `(x, inverted)`. Literals of
+    /// other nodes are taken from `self.best`, the opposite literal of `x` from `own`.
+    fn cover(&self, x: usize, inverted: bool, own: &[Option<Choice>; 2]) -> Option<Choice> {
+        if let (Base::Leaf(_), false) = (self.subject.nodes[x], inverted) {
+            return None;
+        }
+        let key = |c: &Choice| match self.goal {
+            CellGoal::Area => (c.area, c.arrival),
+            CellGoal::Delay => (c.arrival, c.area),
+        };
+        let mut best: Option<Choice> = None;
+        for (i, (pattern, root)) in self.library.patterns.iter().enumerate() {
+            let cell = &self.library.cells[i];
+            let empty = vec![None; cell.pins.len()];
+            'matches: for binds in self.matches(pattern, *root, (x, inverted), x, empty) {
+                let binds: Vec<Literal> = binds.into_iter().map(|f| f.unwrap()).collect();
+                let (mut area, mut arrival) = (cell.area, 0.0f64);
+                for (y, y_inverted) in &binds {
+                    let choice = match (self.subject.nodes[*y], *y_inverted) {
+                        (Base::Leaf(_), false) => continue,
+                        _ if *y == x && *y_inverted == inverted => continue 'matches,
+                        _ if *y == x => own[*y_inverted as usize].as_ref(),
+                        _ => self.best[*y][*y_inverted as usize].as_ref(),
+                    };
+                    let Some(choice) = choice else {
+                        continue 'matches;
+                    };
+                    //a node read by several trees is paid for by its own tree
+                    if self.fanout[*y] == 1 || *y_inverted {
+                        area += choice.area;
+                    }
+                    arrival = arrival.max(choice.arrival);
+                }
+                let choice = Choice {
+                    cell: i,
+                    binds,
+                    area,
+                    arrival: arrival + cell.delay,
+                };
+                if best.as_ref().is_none_or(|b| key(&choice) < key(b)) {
+                    best = Some(choice);
+                }
+            }
+        }
+        best
+    }
+
+    /// Every way node `p` of `pattern` matches the subject literal `at`, as the literal bound
+    /// to each pin. Only `root` may be read from outside the match.
+    fn matches(
+        &self,
+        pattern: &BaseGraph,
+        p: usize,
+        at: Literal,
+        root: usize,
+        binds: Vec<Option<Literal>>,
+    ) -> Vec<Vec<Option<Literal>>> {
+        let ((s, inverted), exclusive) = self.literal_through(at.0, at.1);
+        match pattern.nodes[p] {
+            Base::Leaf(i) => match binds[i] {
+                Some(x) if x != (s, inverted) => vec![],
+                _ => {
+                    let mut binds = binds;
+                    binds[i] = Some((s, inverted));
+                    vec![binds]
+                }
+            },
+            Base::Inv(x) => self.matches(pattern, x, (s, !inverted), root, binds),
+            Base::Nand(x1, x2) => {
+                let Base::Nand(y1, y2) = self.subject.nodes[s] else {
+                    return vec![];
+                };
+                if inverted || !exclusive || s != root && self.fanout[s] != 1 {
+                    return vec![];
+                }
+                let mut ans = Vec::new();
+                for (a, b) in [(y1, y2), (y2, y1)] {
+                    for binds in self.matches(pattern, x1, (a, false), root, binds.clone()) {
+                        ans.extend(self.matches(pattern, x2, (b, false), root, binds));
+                    }
+                }
+                ans
+            }
+        }
+    }
+
+    /// Latest arrival at an output, adding up cell delays.
+    fn arrival(netlist: &Netlist) -> f64 {
+        let mut arrival: HashMap<usize, f64> = HashMap::new();
+        let at = |s: &Signal, arrival: &HashMap<usize, f64>| match s {
+            Signal::Wire(x) => arrival[x],
+            _ => 0.0,
+        };
+        for gate in &netlist.gates {
+            let delay = netlist.cell(&gate.kind).map_or(0.0, |c| c.delay);
+            let latest = gate
+                .inputs
+                .iter()
+                .map(|f| at(f, &arrival))
+                .fold(0.0, f64::max);
+            arrival.insert(gate.id, latest + delay);
+        }
+        netlist.sinks().map(|f| at(f, &arrival)).fold(0.0, f64::max)
+    }
+
+    /// Adds the cells chosen for literal `x`, and the literals they read, to `netlist`.
+    fn emit(
+        &self,
+        x: Literal,
+        emitted: &mut HashMap<Literal, Signal>,
+        netlist: &mut Netlist,
+    ) -> Result<Signal, String> {
+        if let (Base::Leaf(i), false) = (self.subject.nodes[x.0], x.1) {
+            return Ok(self.leaves[i].clone());
+        }
+        if let Some(signal) = emitted.get(&x) {
+            return Ok(signal.clone());
+        }
+        let choice = self.best[x.0][x.1 as usize].as_ref().ok_or_else(|| {
+            "The cell library can't cover the circuit, it needs at least an inverter and a nand"
+                .to_string()
+        })?;
+        let inputs = choice
+            .binds
+            .iter()
+            .map(|b| self.emit(*b, emitted, netlist))
+            .collect::<Result<Vec<_>, _>>()?;
+        let id = netlist.gates.len();
+        netlist.gates.push(Gate {
+            id,
+            kind: self.library.cells[choice.cell].name.to_string(),
+            operator: None,
+            inputs,
+        });
+        emitted.insert(x, Signal::Wire(id));
+        Ok(Signal::Wire(id))
+    }
+}
@@ -0,0 +1,416 @@
+use super::{
+    bus::parse_select,
+    cells::CellGoal,
+    context::{ContextManager, ContextType},
+    equiv,
+    export::{tree_to_dot, ExportFormat},
+    format::{IntegerFormat, Notation},
+    fsm::{FsmEncoding, NO_FSM},
+    funcs::check_verilog_operators,
+    minimize::Minimizer,
+    node::CalcNode,
+    sim,
+    stats::Stats,
+    testbench::testbench,
+    trace,
+    truth::{TableStyle, TruthTable},
+};
+
+/// Runs a `:command` line against the session. Returns `None` when `line` is not a command,
+/// otherwise the text to show the user (possibly empty).
+pub fn run_command(line: &str, ctx: &mut ContextManager) -> Option<String> {
+    let line = line.trim().strip_prefix(':')?;
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let args = args.trim();
+
+    let ans = match name {
+        "balance" => on_off(args).map(|x| {
+            ctx.verilog.balance = x;
+            String::new()
+        }),
+        "bus" => bus_command(args, ctx),
+        "equiv" => equiv_command(args, ctx),
+        "export" => export_command(args, ctx),
+        "format" => format_command(args, ctx),
+        "fsm" => fsm_command(args, ctx),
+        "precision" => precision_command(args, ctx),
+        "separators" => on_off(args).map(|x| {
+            ctx.format.separators = x;
+            String::new()
+        }),
+        "cells" => cells_command(args, ctx),
+        "debug" => debug_command(args),
+        "dot" => dot_command(args, ctx),
+        "minimize" => Minimizer::try_from(args).map(|x| {
+            ctx.verilog.minimize = x;
+            String::new()
+        }),
+        "module" => module_command(args, ctx),
+        "name" => name_command(args, ctx),
+        "ports" => ports_command(args, ctx),
+        "sim" => sim_command(args, ctx),
+        "stats" => stats_command(args, ctx),
+        "testbench" => testbench_command(args, ctx),
+        "truth" => truth_command(args, ctx),
+        "trim" => on_off(args).map(|x| {
+            ctx.format.trim = x;
+            String::new()
+        }),
+        _ => Err(format!("Unknown command: {}", name)),
+    };
+    Some(ans.unwrap_or_else(|e| e))
+}
+
+fn format_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    if args.is_empty() {
+        return Ok(ctx.format.to_string());
+    }
+    if let Ok(x) = Notation::try_from(args) {
+        ctx.format.notation = x;
+        ctx.format.base = IntegerFormat::Decimal;
+    } else {
+        ctx.format.base = IntegerFormat::try_from(args)?;
+    }
+    Ok(String::new())
+}
+
+fn precision_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    ctx.format.precision = match args {
+        "off" => None,
+        x => match x.parse::<usize>() {
+            Ok(p) if p > 0 => Some(p),
+            _ => {
+                return Err(format!(
+                    "Precision must be a positive number or off, got {}",
+                    x
+                ))
+            }
+        },
+    };
+    Ok(String::new())
+}
+
+fn on_off(args: &str) -> Result<bool, String> {
+    match args {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        x => Err(format!("Expected on or off, got {}", x)),
+    }
+}
+
+fn debug_command(args: &str) -> Result<String, String> {
+    let enabled = if args.is_empty() {
+        !trace::enabled()
+    } else {
+        on_off(args)?
+    };
+    trace::set_enabled(enabled);
+    Ok(format!("debug {}", if enabled { "on" } else { "off" }))
+}
+
+fn name_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    if args.is_empty() {
+        return Ok(format!("module {}", ctx.verilog.module));
+    }
+    if args.split_whitespace().count() != 1 {
+        return Err(format!(
+            "Module name must be a single identifier, got {}",
+            args
+        ));
+    }
+    ctx.verilog.module = args.to_string();
+    Ok(String::new())
+}
+
+/// `:ports a b c -> y` fixes the input port order and names the output, `:ports` alone goes
+/// back to inferring them.
+fn ports_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    let (inputs, output) = match args.split_once("->") {
+        Some((i, o)) => {
+            let o = o.trim();
+            if o.split_whitespace().count() != 1 {
+                return Err(format!("Expected a single output name, got {}", o));
+            }
+            (i, Some(o.to_string()))
+        }
+        None => (args, None),
+    };
+    ctx.verilog.inputs = inputs
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+        .map(|f| f.to_string())
+        .collect();
+    ctx.verilog.output = output;
+    Ok(String::new())
+}
+
+/// `:bus a[7:0] b[3:0]` declares buses, redeclaring one changes its range. `:bus` alone
+/// lists them.
+fn bus_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    if args.is_empty() {
+        let buses: Vec<String> = ctx
+            .verilog
+            .buses
+            .iter()
+            .map(|(name, range)| format!("{}{}", name, range))
+            .collect();
+        return Ok(buses.join(" "));
+    }
+    for decl in args.split_whitespace() {
+        let (name, range) = parse_select(decl)
+            .ok_or_else(|| format!("Expected a bus like a[7:0], got {}", decl))?;
+        let buses = &mut ctx.verilog.buses;
+        match buses.iter_mut().find(|f| f.0 == name) {
+            Some(x) => x.1 = range,
+            None => buses.push((name.to_string(), range)),
+        }
+    }
+    Ok(String::new())
+}
+
+/// `:cells` lists the loaded cell library, `:cells area` or `:cells delay` sets what mapping
+/// onto it minimizes.
+fn cells_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    if args.is_empty() {
+        return match &ctx.verilog.cells {
+            Some(x) => Ok(format!("{}\nminimizing {}", x, ctx.verilog.cell_goal)),
+            None => Err("No cell library loaded".to_string()),
+        };
+    }
+    ctx.verilog.cell_goal = CellGoal::try_from(args)?;
+    Ok(String::new())
+}
+
+/// `:fsm` emits the machine being described, `:fsm dot` draws its state diagram and
+/// `:fsm binary`, `:fsm onehot` or `:fsm gray` sets how its states are encoded.
+fn fsm_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    match args {
+        "" => ctx.emit_fsm(),
+        "dot" => match &ctx.verilog.fsm {
+            Some(x) => Ok(x.to_dot(ctx.verilog.fsm_encoding)),
+            None => Err(NO_FSM.to_string()),
+        },
+        x => {
+            ctx.verilog.fsm_encoding = FsmEncoding::try_from(x)?;
+            Ok(String::new())
+        }
+    }
+}
+
+/// `:export` names the format emitted netlists are shown in, `:export blif` changes it and
+/// `:export blif top.blif` writes the last emitted netlist to a file in that format.
+fn export_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    if args.is_empty() {
+        return Ok(ctx.verilog.export.to_string());
+    }
+    let (format, path) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let format = ExportFormat::try_from(format)?;
+    let path = path.trim();
+    if path.is_empty() {
+        ctx.verilog.export = format;
+        return Ok(String::new());
+    }
+    let netlist = ctx
+        .netlist
+        .as_ref()
+        .ok_or_else(|| "No module emitted yet".to_string())?;
+    let text = format.exporter().export(netlist)?;
+    std::fs::write(path, text).map_err(|e| format!("Can't write {}: {}", path, e))?;
+    Ok(format!("wrote {}", path))
+}
+
+/// `:module full_adder sum cout` emits one module driving `sum` and `cout`.
+fn module_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    let mut parts = args.split_whitespace();
+    let name = parts
+        .next()
+        .ok_or_else(|| "Usage: :module <name> <output>...".to_string())?;
+    let outputs: Vec<&str> = parts.collect();
+    if outputs.is_empty() {
+        return Err("Usage: :module <name> <output>...".to_string());
+    }
+    ctx.emit_module(name, &outputs)
+}
+
+/// `:sim a=1 b=0` evaluates the last emitted module for one assignment of its inputs,
+/// `:sim vectors.csv` for every row of a CSV file whose header names the inputs.
+fn sim_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    let netlist = ctx
+        .netlist
+        .as_ref()
+        .ok_or_else(|| "Nothing to simulate, emit a module first".to_string())?;
+    if !args.is_empty() && !args.contains('=') {
+        let csv =
+            std::fs::read_to_string(args).map_err(|e| format!("Can't read {}: {}", args, e))?;
+        return sim::run_vectors(netlist, &csv);
+    }
+    let pairs = args
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+        .map(|f| {
+            f.split_once('=')
+                .ok_or_else(|| format!("Expected name=value, got {}", f))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let inputs = sim::assign_inputs(netlist, &pairs)?;
+    sim::simulate(netlist, &inputs)
+}
+
+/// `:testbench` shows the testbench of the last emitted module. `:testbench on [dir]` writes
+/// every module emitted from then on to `dir` with its testbench, `:testbench vectors N` sets
+/// how many random vectors wide modules, and modules with registers, are driven with.
+fn testbench_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    let (name, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+    match name {
+        "" => {
+            let netlist = ctx
+                .netlist
+                .as_ref()
+                .ok_or_else(|| "No module emitted yet".to_string())?;
+            return testbench(netlist, ctx.verilog.random_vectors);
+        }
+        "on" => ctx.verilog.testbench = Some(if rest.is_empty() { "." } else { rest }.to_string()),
+        "off" => ctx.verilog.testbench = None,
+        "vectors" => {
+            ctx.verilog.random_vectors = match rest.parse::<usize>() {
+                Ok(x) if x > 0 => x,
+                _ => {
+                    return Err(format!(
+                        "Expected a positive number of vectors, got {}",
+                        rest
+                    ))
+                }
+            }
+        }
+        x => return Err(format!("Expected on, off or vectors, got {}", x)),
+    }
+    Ok(String::new())
+}
+
+/// Parses `expr` and evaluates it in the current context, giving its gate tree.
+fn lower(expr: &str, ctx: &mut ContextManager) -> Result<CalcNode, String> {
+    let tree = crate::parse(expr).ok_or_else(|| "Expected an expression".to_string())?;
+    if !matches!(ctx.specific(), ContextType::Calculate) {
+        check_verilog_operators(&tree)?;
+    }
+    Ok(ctx.eval(&tree))
+}
+
+fn require_verilog(ctx: &ContextManager, command: &str) -> Result<(), String> {
+    match ctx.specific() {
+        ContextType::Calculate => Err(format!(":{} needs a verilog context", command)),
+        _ => Ok(()),
+    }
+}
+
+/// `:dot expr` draws the tree `expr` parses to, `:dot eval expr` the tree it evaluates to in
+/// the current context, with gates shared by id.
+fn dot_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    let (evaluate, expr) = match args.strip_prefix("eval ") {
+        Some(x) => (true, x),
+        None => (false, args),
+    };
+    if evaluate {
+        return Ok(tree_to_dot(&lower(expr, ctx)?, true));
+    }
+    let tree = crate::parse(expr).ok_or_else(|| "Expected an expression".to_string())?;
+    Ok(tree_to_dot(&tree, false))
+}
+
+/// `:truth [table|csv|md] expr`
+fn truth_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    require_verilog(ctx, "truth")?;
+    let (style, expr) = match args.split_once(char::is_whitespace) {
+        Some((first, rest)) if TableStyle::try_from(first).is_ok() => {
+            (TableStyle::try_from(first)?, rest)
+        }
+        _ => (TableStyle::Aligned, args),
+    };
+    let tree = lower(expr, ctx)?;
+    let output = ctx.verilog.output.as_deref().unwrap_or("y").to_string();
+    Ok(TruthTable::build(&tree)?.render(style, &output))
+}
+
+/// Splits `args` at its first comma outside parentheses.
+fn split_top_level(args: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 => return Some((&args[..i], &args[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Runs `f` on `expr` in the verilog context named by a leading `[context]`, or in the current
+/// one if there is none.
+fn with_context<T>(
+    expr: &str,
+    ctx: &mut ContextManager,
+    command: &str,
+    f: impl FnOnce(&str, &mut ContextManager) -> Result<T, String>,
+) -> Result<T, String> {
+    let expr = expr.trim();
+    let Some((name, rest)) = expr.strip_prefix('[').and_then(|f| f.split_once(']')) else {
+        require_verilog(ctx, command)?;
+        return f(expr, ctx);
+    };
+    let typ = ContextType::try_from(name.trim())
+        .map_err(|_| format!("No associated context found: {}", name.trim()))?;
+    ctx.push_stack_frame(typ);
+    let ans = require_verilog(ctx, command).and_then(|_| f(rest, ctx));
+    ctx.pop_stack_frame();
+    ans
+}
+
+/// `:equiv [context] e1, [context] e2`
+fn equiv_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    let (lhs, rhs) = split_top_level(args).ok_or_else(|| "Expected two expressions".to_string())?;
+    let lhs = with_context(lhs, ctx, "equiv", lower)?;
+    let rhs = with_context(rhs, ctx, "equiv", lower)?;
+    Ok(equiv::check(&lhs, &rhs)?.to_string())
+}
+
+fn netlist_stats(expr: &str, ctx: &mut ContextManager) -> Result<Stats, String> {
+    let tree = lower(expr, ctx)?;
+    Ok(Stats::of(&ctx.netlist_of(&tree)?))
+}
+
+/// `:stats` reports on the last emitted module, `:stats [context] expr` on an expression and
+/// `:stats compare expr` sets the gate-level lowerings of an expression side by side.
+fn stats_command(args: &str, ctx: &mut ContextManager) -> Result<String, String> {
+    if args.is_empty() {
+        let netlist = ctx
+            .netlist
+            .as_ref()
+            .ok_or_else(|| "No module emitted yet".to_string())?;
+        return Ok(Stats::of(netlist).to_string());
+    }
+    let Some(expr) = args.strip_prefix("compare ") else {
+        return with_context(args, ctx, "stats", netlist_stats).map(|f| f.to_string());
+    };
+    let mut rows = vec![[
+        "context".to_string(),
+        "gates".to_string(),
+        "depth".to_string(),
+        "transistors".to_string(),
+    ]];
+    for name in ["verilog", "verilog nand", "verilog nor"] {
+        let stats = with_context(&format!("[{}] {}", name, expr), ctx, "stats", netlist_stats)?;
+        rows.push([
+            name.to_string(),
+            stats.gate_count().to_string(),
+            stats.depth.to_string(),
+            stats.transistors.to_string(),
+        ]);
+    }
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|row| format!("{:<12} {:>5} {:>5} {:>11}", row[0], row[1], row[2], row[3]))
+        .collect();
+    Ok(lines.join("\n"))
+}
@@ -1,13 +1,23 @@
-use std::fmt::Write;
+use std::fs;
+use std::path::Path;
 use std::sync::atomic::Ordering;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     sync::{atomic::AtomicUsize, Arc},
 };
 
 use super::{
+    bus::{bit_name, split_bit, Blaster, BusRange},
+    cells::{CellGoal, CellLibrary, Mapper},
+    export::ExportFormat,
+    format::NumberFormat,
+    fsm::{Fsm, FsmEncoding, NO_FSM, STATE},
     funcs::{assemble_map_calc, assemble_map_veri, assemble_map_veri_nand, assemble_map_veri_nor},
+    minimize::{espresso, quine_mccluskey, Cube, Minimizer, EXACT_LIMIT, INPUT_LIMIT},
+    netlist::{FlipFlop, Netlist, VerilogStyle},
     node::{CalcFunctionData, CalcNode, CalcUserFunctionData},
+    seq::Register,
+    testbench::testbench,
     EvalFunction,
 };
 
@@ -17,6 +27,10 @@ pub enum ContextType {
     Verilog,
     VerilogNand,
     VerilogNor,
+    /// Same gates as `Verilog`, emitted as continuous assignments.
+    VerilogAssign,
+    /// Same gates as `Verilog`, mapped onto the loaded cell library.
+    VerilogCells,
 }
 
 impl Default for ContextType {
@@ -34,6 +48,8 @@ impl TryFrom<String> for ContextType {
             _ if value == "verilog" => Ok(ContextType::Verilog),
             _ if value == "verilog nand" => Ok(ContextType::VerilogNand),
             _ if value == "verilog nor" => Ok(ContextType::VerilogNor),
+            _ if value == "verilog assign" => Ok(ContextType::VerilogAssign),
+            _ if value == "verilog cells" => Ok(ContextType::VerilogCells),
             _ => Err(()),
         }
     }
@@ -48,6 +64,8 @@ impl TryFrom<&str> for ContextType {
             _ if value == "verilog" => Ok(ContextType::Verilog),
             _ if value == "verilog nand" => Ok(ContextType::VerilogNand),
             _ if value == "verilog nor" => Ok(ContextType::VerilogNor),
+            _ if value == "verilog assign" => Ok(ContextType::VerilogAssign),
+            _ if value == "verilog cells" => Ok(ContextType::VerilogCells),
             _ => Err(()),
         }
     }
@@ -61,111 +79,90 @@ pub struct Context {
 }
 
 impl Context {
-    pub fn print_result(&self, ans: &CalcNode) {
-        match self.specific {
-            ContextType::Calculate => {
-                println!("{:?}", ans);
-                // println!("{}", ans);
-                // println!("{:#}", ans);
-            }
-            ContextType::Verilog | ContextType::VerilogNand | ContextType::VerilogNor => {
-                // println!("{:?}", i);
-                // println!("{:#?}", ans);
-                println!("{}", self.to_verilog(ans));
+    pub fn format_result(&self, ans: &CalcNode, format: &NumberFormat) -> String {
+        match ans {
+            CalcNode::SingleValue(x) => format.render(*x),
+            CalcNode::MultipleValue(x) => {
+                let parts: Vec<String> = x.iter().map(|f| format.render(*f)).collect();
+                format!("[{}]", parts.join(", "))
             }
+            CalcNode::NoValue => String::new(),
+            _ => format!("{:#}", ans),
         }
-
-        // todo!()
     }
+}
 
-    fn to_verilog(&self, node: &CalcNode) -> String {
-        let mut t = String::new();
-        let mut seen = HashSet::new();
-        let mut infos = HashMap::new();
-        self.to_verilog__(node, &mut t, &mut seen, &mut infos);
+/// Session settings for the modules emitted by the Verilog contexts.
+#[derive(Debug, Clone)]
+pub struct VerilogOptions {
+    pub module: String,
+    /// User supplied input port order, inferred inputs not listed here follow them.
+    pub inputs: Vec<String>,
+    /// Output port for expressions that are not a definition.
+    pub output: Option<String>,
+    pub minimize: Minimizer,
+    /// Declared buses, in declaration order.
+    pub buses: Vec<(String, BusRange)>,
+    /// Directory every emitted module is written to along with its testbench, if any.
+    pub testbench: Option<String>,
+    /// Vectors a testbench drives when there are too many inputs to drive them all.
+    pub random_vectors: usize,
+    /// Rebuild chains of associative gates as balanced trees.
+    pub balance: bool,
+    /// Library the `verilog cells` context maps onto.
+    pub cells: Option<CellLibrary>,
+    pub cell_goal: CellGoal,
+    /// Format emitted netlists are shown in.
+    pub export: ExportFormat,
+    /// Declared registers, in declaration order.
+    pub registers: Vec<Register>,
+    /// Machine being described, if any.
+    pub fsm: Option<Fsm>,
+    pub fsm_encoding: FsmEncoding,
+}
 
-        print!("wire ");
-        for (pos, t) in seen.iter().enumerate() {
-            print!("w_{}", t);
-            if pos != seen.len() - 1 {
-                print!(", ");
-            } else {
-                println!(";");
-            }
+impl Default for VerilogOptions {
+    fn default() -> Self {
+        VerilogOptions {
+            module: "top".to_string(),
+            inputs: Vec::new(),
+            output: None,
+            minimize: Minimizer::Off,
+            buses: Vec::new(),
+            testbench: None,
+            random_vectors: 256,
+            balance: false,
+            cells: None,
+            cell_goal: CellGoal::Area,
+            export: ExportFormat::Verilog,
+            registers: Vec::new(),
+            fsm: None,
+            fsm_encoding: FsmEncoding::Binary,
         }
-
-        return t;
-    }
-
-    fn to_verilog__(
-        &self,
-        node: &CalcNode,
-        sout: &mut String,
-        seen: &mut HashSet<usize>,
-        info: &mut HashMap<usize, CalcFunctionData>,
-    ) {
-        match node {
-            CalcNode::Function(x) => {
-                x.params
-                    .iter()
-                    .for_each(|y| self.to_verilog__(y, sout, seen, info));
-
-                if !seen.insert(x.id) {
-                    return;
-                }
-
-                if x.params.len() == 2 {
-                    write!(
-                        sout,
-                        "{}({}, {}, {});\n",
-                        x.name,
-                        format!("w_{}", x.id),
-                        if let CalcNode::Text(y) = &x.params[0] {
-                            format!("{}", y)
-                        } else if let CalcNode::Function(y) = &x.params[0] {
-                            format!("w_{}", y.id)
-                        } else {
-                            panic!("Type not allowed")
-                        },
-                        if let CalcNode::Text(y) = &x.params[1] {
-                            format!("{}", y)
-                        } else if let CalcNode::Function(y) = &x.params[1] {
-                            format!("w_{}", y.id)
-                        } else {
-                            panic!("Type not allowed")
-                        }
-                    )
-                    .expect("msg");
-                } else if x.params.len() == 1 {
-                    write!(
-                        sout,
-                        "{}({}, {});\n",
-                        x.name,
-                        format!("w_{}", x.id),
-                        if let CalcNode::Text(y) = &x.params[0] {
-                            format!("{}", y)
-                        } else if let CalcNode::Function(y) = &x.params[0] {
-                            format!("w_{}", y.id)
-                        } else {
-                            panic!("Type not allowed")
-                        }
-                    )
-                    .expect("msg");
-                }
-            }
-            _ => {}
-        };
     }
 }
 
 pub struct ContextManager {
     contexts: Vec<Context>,
+    pub format: NumberFormat,
+    pub verilog: VerilogOptions,
+    /// Netlist of the last module emitted.
+    pub netlist: Option<Netlist>,
+    /// User function each gate of the last evaluated tree was computed by, innermost first.
+    wire_labels: HashMap<usize, String>,
+    /// Stack frames pushed to bind the parameters of the user functions being evaluated.
+    call_frames: usize,
 }
 
 impl ContextManager {
     pub fn new() -> ContextManager {
         ContextManager {
             contexts: Vec::new(),
+            format: NumberFormat::default(),
+            verilog: VerilogOptions::default(),
+            netlist: None,
+            wire_labels: HashMap::new(),
+            call_frames: 0,
         }
     }
 
@@ -186,6 +183,11 @@ impl ContextManager {
                 specific: typ,
                 user_def: HashMap::new(),
             },
+            ContextType::VerilogAssign | ContextType::VerilogCells => Context {
+                built_in: assemble_map_veri(),
+                specific: typ,
+                user_def: HashMap::new(),
+            },
             ContextType::VerilogNor => Context {
                 built_in: assemble_map_veri_nor(),
                 specific: typ,
@@ -195,6 +197,25 @@ impl ContextManager {
         self.contexts.push(ct);
     }
 
+    /// Loads the cell library at `path` and switches to the `verilog cells` context.
+    pub fn push_cells_frame(&mut self, path: &str) -> Result<(), String> {
+        self.verilog.cells = Some(CellLibrary::load(path)?);
+        self.push_stack_frame(ContextType::VerilogCells);
+        Ok(())
+    }
+
+    /// Declares the register of `reg q <= d on clk`, given without its leading `reg`.
+    /// Redeclaring a register replaces it.
+    pub fn declare_register(&mut self, decl: &str) -> Result<(), String> {
+        let register = Register::try_from(decl)?;
+        let registers = &mut self.verilog.registers;
+        match registers.iter_mut().find(|f| f.name == register.name) {
+            Some(x) => *x = register,
+            None => registers.push(register),
+        }
+        Ok(())
+    }
+
     pub fn pop_stack_frame(&mut self) {
         self.contexts.pop();
     }
@@ -225,13 +246,443 @@ impl ContextManager {
         self.contexts.iter().rev().find_map(|f| f.user_def.get(k))
     }
 
-    pub fn print_result(&self, ans: &CalcNode) {
-        self.get_top().print_result(ans)
+    /// Whether `k` names a parameter of a user function being evaluated.
+    fn is_parameter(&self, k: &'_ str) -> bool {
+        let frame = self
+            .contexts
+            .iter()
+            .rev()
+            .position(|f| f.user_def.contains_key(k));
+        frame.is_some_and(|f| f < self.call_frames)
+    }
+
+    pub fn print_result(&mut self, ans: &CalcNode) {
+        if *ans == CalcNode::NoValue {
+            return;
+        }
+        println!("{}", self.format_result(ans));
+    }
+
+    pub fn format_result(&mut self, ans: &CalcNode) -> String {
+        match self.specific() {
+            ContextType::Calculate => self.get_top().format_result(ans, &self.format),
+            ContextType::Verilog
+            | ContextType::VerilogNand
+            | ContextType::VerilogNor
+            | ContextType::VerilogAssign
+            | ContextType::VerilogCells => self.emit_verilog(ans),
+        }
+    }
+
+    /// Emits `ans` as a complete module. A definition such as `y = a & b` drives an output
+    /// named after it, anything else drives the configured output (`y` by default).
+    pub fn emit_verilog(&mut self, ans: &CalcNode) -> String {
+        match self.netlist_of(ans) {
+            Ok(netlist) => self.finish_netlist(netlist),
+            Err(e) => e,
+        }
+    }
+
+    /// Netlist `emit_verilog` would emit for `ans`, without emitting it.
+    pub fn netlist_of(&mut self, ans: &CalcNode) -> Result<Netlist, String> {
+        let (output, node) = self.resolve_output(ans);
+        let mut netlist = Netlist::new(&self.verilog.module);
+        let counter = Arc::new(AtomicUsize::new(0));
+        self.add_output(&output, node, counter.clone(), &mut netlist)?;
+        self.add_registers(counter, &mut netlist)?;
+        self.run_passes(&mut netlist)?;
+        Ok(netlist)
+    }
+
+    /// Emits one module driving an output for each of the user defined `outputs`. The outputs
+    /// share their inputs, and subexpressions common to several outputs share wires.
+    pub fn emit_module(&mut self, name: &str, outputs: &[&str]) -> Result<String, String> {
+        if let ContextType::Calculate = self.specific() {
+            return Err("Modules can only be emitted in a verilog context".to_string());
+        }
+        let mut netlist = Netlist::new(name);
+        let counter = Arc::new(AtomicUsize::new(0));
+        self.wire_labels.clear();
+        for output in outputs {
+            if self.verilog.registers.iter().any(|f| f.name == *output) {
+                let tree = CalcNode::Text(output.to_string());
+                self.add_output(output, tree, counter.clone(), &mut netlist)?;
+                continue;
+            }
+            match self.get_user_def(output) {
+                None => return Err(format!("No definition found for {}", output)),
+                Some(x) if !x.params.is_empty() => {
+                    return Err(format!(
+                        "{} takes parameters and can't be an output",
+                        output
+                    ))
+                }
+                Some(_) => {}
+            }
+            let tree = self.eval_internal(
+                &CalcNode::Function(CalcFunctionData::new(output)),
+                counter.clone(),
+            );
+            self.add_output(output, tree, counter.clone(), &mut netlist)?;
+        }
+        self.add_registers(counter, &mut netlist)?;
+        self.run_passes(&mut netlist)?;
+        Ok(self.finish_netlist(netlist))
+    }
+
+    /// Adds `output` to `netlist`, one port bit per bit of the expression when it involves
+    /// buses. An output declared as a bus is truncated or zero-extended to its width.
+    fn add_output(
+        &self,
+        output: &str,
+        node: CalcNode,
+        counter: Arc<AtomicUsize>,
+        netlist: &mut Netlist,
+    ) -> Result<(), String> {
+        let mut blaster = Blaster::new(&self.verilog.buses, counter.clone());
+        let declared = self.verilog.buses.iter().find(|f| f.0 == output);
+        if !blaster.is_needed(&node) && declared.is_none() {
+            //the passes rebuilding the tree give it ids the labels don't refer to
+            if self.verilog.minimize == Minimizer::Off {
+                self.label_gates(&node, netlist);
+            }
+            netlist.references.push((output.to_string(), node.clone()));
+            let node = self.prepare_output(output, node, counter, netlist);
+            netlist.add_output(output, &node);
+            return Ok(());
+        }
+        //a declared output is part of the context that sizes the expression
+        let own = blaster.width(&node)?;
+        if own == 0 {
+            return Err(format!("{} has no bits", output));
+        }
+        let range = match declared {
+            Some((_, r)) => *r,
+            None => BusRange {
+                msb: own - 1,
+                lsb: 0,
+            },
+        };
+        let mut bits = blaster.blast(&node, own.max(range.width()))?;
+        bits.truncate(range.width());
+        for (bit, node) in range.bits().zip(bits) {
+            let name = bit_name(output, bit);
+            netlist.references.push((name.to_string(), node.clone()));
+            let node = self.prepare_output(&name, node, counter.clone(), netlist);
+            netlist.add_output(&name, &node);
+        }
+        Ok(())
+    }
+
+    /// Emits the machine described with `fsm`, `state` and `trans`. The state register is
+    /// named `state`, and the reset loads the first state.
+    pub fn emit_fsm(&mut self) -> Result<String, String> {
+        if let ContextType::Calculate = self.specific() {
+            return Err("Machines can only be emitted in a verilog context".to_string());
+        }
+        let fsm = self.verilog.fsm.clone().ok_or_else(|| NO_FSM.to_string())?;
+        let encoding = self.verilog.fsm_encoding;
+        let logic = fsm.logic(encoding)?;
+        let mut netlist = Netlist::new(&fsm.name);
+        netlist
+            .notes
+            .push(format!("states: {}", fsm.codes(encoding)));
+        let counter = Arc::new(AtomicUsize::new(0));
+        self.wire_labels.clear();
+        for (name, tree) in &logic.outputs {
+            let node = self.eval_internal(tree, counter.clone());
+            netlist.references.push((name.to_string(), node.clone()));
+            netlist.add_output(name, &node);
+        }
+        for (i, tree) in logic.next.iter().enumerate() {
+            let node = self.eval_internal(tree, counter.clone());
+            let name = bit_name(STATE, i);
+            netlist.register_references.push((name, node.clone()));
+            let reset = (fsm.reset.to_string(), encoding.code(0) >> i & 1 == 1);
+            netlist.add_register(&bit_name(STATE, i), &node, &fsm.clock, Some(reset));
+        }
+        netlist.connect_registers(&fsm.clock);
+        self.run_passes(&mut netlist)?;
+        Ok(self.finish_netlist(netlist))
+    }
+
+    /// Runs a line of the machine description sub-language, a `fsm`, `state` or `trans` line.
+    /// Returns `None` for any other line, including expressions starting with those words.
+    pub fn fsm_line(&mut self, line: &str) -> Option<Result<(), String>> {
+        let (keyword, rest) = line.trim().split_once(char::is_whitespace)?;
+        if !Fsm::is_line(keyword, rest) {
+            return None;
+        }
+        let fsm = self.verilog.fsm.as_mut().ok_or_else(|| NO_FSM.to_string());
+        let ans = match keyword {
+            "fsm" => Fsm::declare(rest).map(|f| self.verilog.fsm = Some(f)),
+            "state" => fsm.and_then(|f| f.add_state(rest)),
+            "trans" => fsm.and_then(|f| f.add_transition(rest)),
+            _ => return None,
+        };
+        Some(ans)
+    }
+
+    /// Adds the registers `netlist` reads, and those their next values read in turn. Each bit
+    /// of a register is a flip-flop whose next value is added like an output. The clocks and
+    /// resets become inputs, and `prev` registers take the clock of the first register.
+    fn add_registers(
+        &mut self,
+        counter: Arc<AtomicUsize>,
+        netlist: &mut Netlist,
+    ) -> Result<(), String> {
+        let mut added: Vec<String> = Vec::new();
+        loop {
+            let read = self.verilog.registers.iter().find(|r| {
+                !added.contains(&r.name) && netlist.inputs.iter().any(|f| split_bit(f).0 == r.name)
+            });
+            let Some(register) = read.cloned() else {
+                break;
+            };
+            added.push(register.name.to_string());
+            let tree = self.eval_internal(&register.next, counter.clone());
+            let first = netlist.outputs.len();
+            let first_reference = netlist.references.len();
+            self.add_output(&register.name, tree, counter.clone(), netlist)?;
+            let bits = netlist.outputs.split_off(first);
+            let references = netlist.references.split_off(first_reference);
+            netlist.register_references.extend(references);
+            let flops = bits.into_iter().enumerate().map(|(i, (name, d))| FlipFlop {
+                name,
+                d,
+                clock: register.clock.to_string(),
+                reset: register
+                    .reset
+                    .as_ref()
+                    .map(|(signal, value)| (signal.to_string(), i < 64 && (value >> i) & 1 == 1)),
+            });
+            netlist.flops.extend(flops);
+        }
+
+        let clock = match self.verilog.registers.first() {
+            Some(x) => x.clock.to_string(),
+            None => "clk".to_string(),
+        };
+        netlist.connect_registers(&clock);
+        Ok(())
+    }
+
+    /// Labels the gates of `node` with the user functions computing them.
+    fn label_gates(&self, node: &CalcNode, netlist: &mut Netlist) {
+        if let CalcNode::Function(x) = node {
+            if let Some(label) = self.wire_labels.get(&x.id) {
+                netlist.labels.insert(x.id, label.to_string());
+            }
+            for param in &x.params {
+                self.label_gates(param, netlist);
+            }
+        }
+    }
+
+    /// Passes run on the expression of each output before it is added to `netlist`. The inputs
+    /// of the original expression stay ports even when a pass removes them.
+    fn prepare_output(
+        &self,
+        output: &str,
+        node: CalcNode,
+        counter: Arc<AtomicUsize>,
+        netlist: &mut Netlist,
+    ) -> CalcNode {
+        if self.verilog.minimize == Minimizer::Off {
+            return node;
+        }
+        let mut original = Netlist::new(output);
+        original.add_output(output, &node);
+        for input in original.inputs {
+            if !netlist.inputs.contains(&input) {
+                netlist.inputs.push(input);
+            }
+        }
+        match self.minimize(output, &node, counter) {
+            Ok((tree, note)) => {
+                netlist.notes.push(note);
+                tree
+            }
+            Err(e) => {
+                netlist
+                    .notes
+                    .push(format!("{}: not minimized, {}", output, e));
+                node
+            }
+        }
+    }
+
+    /// Rebuilds `node` as a minimum sum of products of its inputs, through this context's
+    /// and/or/not built-ins so the result is lowered like anything else typed here.
+    fn minimize(
+        &self,
+        output: &str,
+        node: &CalcNode,
+        counter: Arc<AtomicUsize>,
+    ) -> Result<(CalcNode, String), String> {
+        let mut single = Netlist::new(output);
+        single.add_output(output, node);
+        if !single.flops.is_empty() {
+            return Err("it reads a previous value".to_string());
+        }
+        let n = single.inputs.len();
+        if n > INPUT_LIMIT {
+            return Err(format!("{} inputs is more than {}", n, INPUT_LIMIT));
+        }
+
+        let mut minterms = Vec::new();
+        for m in 0..1u32 << n {
+            let assignment = single
+                .inputs
+                .iter()
+                .enumerate()
+                .map(|(i, f)| (f.to_string(), m & (1 << (n - 1 - i)) != 0))
+                .collect();
+            if single.output_values(&assignment)?[0] {
+                minterms.push(m);
+            }
+        }
+
+        let exact = match self.verilog.minimize {
+            Minimizer::Exact => true,
+            Minimizer::Heuristic => false,
+            _ => n <= EXACT_LIMIT,
+        };
+        let cover = if exact {
+            quine_mccluskey(n, &minterms)
+        } else {
+            espresso(n, &minterms)
+        };
+        let literals: usize = cover.iter().map(|f| f.literals(n)).sum();
+        let note = format!(
+            "{}: minimized to {} product terms, {} literals ({})",
+            output,
+            cover.len(),
+            literals,
+            if exact { "quine-mccluskey" } else { "espresso" }
+        );
+        Ok((self.sum_of_products(&single.inputs, &cover, counter), note))
+    }
+
+    fn sum_of_products(
+        &self,
+        inputs: &[String],
+        cover: &[Cube],
+        counter: Arc<AtomicUsize>,
+    ) -> CalcNode {
+        let built_in = |name: &str| {
+            self.get_built_in(name)
+                .unwrap_or_else(|| panic!("No {} gate in this context", name))
+        };
+        let (and, or, not) = (built_in("and"), built_in("or"), built_in("not"));
+
+        let mut products = Vec::new();
+        for cube in cover {
+            let literals = cube.terms(inputs.len()).into_iter().map(|(i, positive)| {
+                let input = CalcNode::Text(inputs[i].to_string());
+                if positive {
+                    input
+                } else {
+                    not(vec![input], counter.clone())
+                }
+            });
+            match literals.reduce(|x, y| and(vec![x, y], counter.clone())) {
+                Some(x) => products.push(x),
+                None => return CalcNode::SingleValue(1.0),
+            }
+        }
+        //or the products pairwise, a chain of thousands of them would be as deep
+        while products.len() > 1 {
+            products = products
+                .chunks(2)
+                .map(|f| match f {
+                    [x, y] => or(vec![x.clone(), y.clone()], counter.clone()),
+                    _ => f[0].clone(),
+                })
+                .collect();
+        }
+        products.pop().unwrap_or(CalcNode::SingleValue(0.0))
+    }
+
+    /// Passes run on every netlist before it is emitted.
+    fn run_passes(&self, netlist: &mut Netlist) -> Result<(), String> {
+        if let ContextType::VerilogAssign = self.specific() {
+            netlist.style = VerilogStyle::Assign;
+        }
+        //a bus is a port as a whole even when only some of its bits are used
+        for (name, range) in &self.verilog.buses {
+            if netlist.inputs.iter().any(|f| split_bit(f).0 == name) {
+                for bit in range.bits().map(|i| bit_name(name, i)) {
+                    if !netlist.inputs.contains(&bit) {
+                        netlist.inputs.push(bit);
+                    }
+                }
+            }
+        }
+        netlist.order_inputs(&self.verilog.inputs);
+        let (before, after) = netlist.hash_cons();
+        netlist.notes.push(format!(
+            "gates: {} before hash-consing, {} after",
+            before, after
+        ));
+        if self.verilog.balance {
+            let (before, after) = netlist.balance();
+            netlist.notes.push(format!(
+                "depth: {} before balancing, {} after, {} saved",
+                before,
+                after,
+                before - after
+            ));
+        }
+        if let ContextType::VerilogCells = self.specific() {
+            let library = self.verilog.cells.as_ref().ok_or_else(|| {
+                "No cell library loaded, use context verilog cells <file>".to_string()
+            })?;
+            *netlist = Mapper::new(library, self.verilog.cell_goal).map(netlist)?;
+        }
+        netlist.number_wires();
+        Ok(())
+    }
+
+    /// Emits `netlist` in the export format, writing it and its testbench out as Verilog when
+    /// asked to. The netlist is kept for `:sim`.
+    fn finish_netlist(&mut self, mut netlist: Netlist) -> String {
+        let bench = self.verilog.testbench.as_ref().map(|dir| {
+            let path = |suffix| Path::new(dir).join(format!("{}{}.v", netlist.name, suffix));
+            (path(""), path("_tb"))
+        });
+        if let Some((_, path)) = &bench {
+            netlist.notes.push(format!("testbench: {}", path.display()));
+        }
+        if let Some((module, path)) = bench {
+            let written = testbench(&netlist, self.verilog.random_vectors).and_then(|tb| {
+                fs::write(&module, netlist.to_verilog())
+                    .and_then(|_| fs::write(&path, tb))
+                    .map_err(|e| e.to_string())
+            });
+            if let Err(e) = written {
+                netlist.notes.push(format!("testbench not written, {}", e));
+            }
+        }
+        let ans = self.verilog.export.exporter().export(&netlist);
+        self.netlist = Some(netlist);
+        ans.unwrap_or_else(|e| e)
+    }
+
+    fn resolve_output(&mut self, ans: &CalcNode) -> (String, CalcNode) {
+        if let CalcNode::Function(x) = ans {
+            if self.get_built_in(&x.name).is_none() && self.get_user_def(&x.name).is_some() {
+                return (x.name.to_string(), self.eval(ans));
+            }
+        }
+        let output = self.verilog.output.as_deref().unwrap_or("y");
+        (output.to_string(), ans.clone())
     }
 }
 
 impl ContextManager {
     pub fn eval(&mut self, node: &CalcNode) -> CalcNode {
+        self.wire_labels.clear();
         self.eval_internal(node, Arc::new(AtomicUsize::new(0)))
     }
 
@@ -248,6 +699,7 @@ impl ContextManager {
                     return t(asd, counter);
                 }
 
+                let parameter = self.is_parameter(&x.name);
                 if let Some(t) = self.get_user_def(x.name.as_str()) {
                     //do a sub and put in
                     // context.push_stack_frame(context.get_top().specific);
@@ -258,25 +710,31 @@ impl ContextManager {
                     let ans;
                     if t.params.len() > 0 {
                         self.push_stack_frame(self.specific());
-                        for i in 0..t.params.len() {
-                            if i < x.params.len() {
-                                self.push_user_def(
-                                    t.params[i].to_string(),
-                                    CalcUserFunctionData {
-                                        name: t.params[i].to_string(),
-                                        id: counter.fetch_add(1, Ordering::SeqCst),
-                                        params: Vec::new(),
-                                        eval_tree: Box::new(x.params[i].clone()),
-                                    },
-                                )
-                            }
+                        self.call_frames += 1;
+                        for (param, arg) in t.params.iter().zip(asd) {
+                            self.push_user_def(
+                                param.to_string(),
+                                CalcUserFunctionData {
+                                    name: param.to_string(),
+                                    id: counter.fetch_add(1, Ordering::SeqCst),
+                                    params: Vec::new(),
+                                    eval_tree: Box::new(arg),
+                                },
+                            )
                         }
                         ans = self.eval_internal(&t.eval_tree, counter);
+                        self.call_frames -= 1;
                         self.pop_stack_frame();
                     } else {
                         ans = self.eval_internal(&t.eval_tree, counter);
                     }
 
+                    //gates named by an inner function keep that name
+                    if let (CalcNode::Function(f), false) = (&ans, parameter) {
+                        self.wire_labels
+                            .entry(f.id)
+                            .or_insert_with(|| t.name.to_string());
+                    }
                     return ans;
                 } else {
                     return CalcNode::Text(x.name.to_string());
@@ -298,6 +756,11 @@ impl ContextManager {
                 })
             }
             CalcNode::SingleValue(x) => CalcNode::SingleValue(*x),
+            //a parameter is bound to its argument, already evaluated by the caller
+            CalcNode::Text(x) if self.is_parameter(x) => self
+                .get_user_def(x)
+                .map(|f| *f.eval_tree.clone())
+                .unwrap_or_else(|| CalcNode::Text(x.to_string())),
             CalcNode::Text(x) => CalcNode::Text(x.to_string()),
             _ => panic!("Unexpected"),
         }
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{
+    bdd::Bdd,
+    node::CalcNode,
+    truth::{inputs_of, CalcEvaluator},
+};
+
+/// Most inputs `check` enumerates the truth table for, a BDD is built above that.
+pub const TABLE_LIMIT: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    TruthTable,
+    Bdd,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::TruthTable => "truth table".fmt(f),
+            Method::Bdd => "bdd".fmt(f),
+        }
+    }
+}
+
+/// Outcome of comparing two gate trees. `counterexample` holds an assignment of `inputs`
+/// with the values each side gives for it, when the two differ.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equivalence {
+    pub inputs: Vec<String>,
+    pub method: Method,
+    pub counterexample: Option<(Vec<bool>, bool, bool)>,
+}
+
+impl fmt::Display for Equivalence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.counterexample {
+            None => write!(
+                f,
+                "equivalent over {} inputs ({})",
+                self.inputs.len(),
+                self.method
+            ),
+            Some((values, lhs, rhs)) => {
+                let assignment: Vec<String> = self
+                    .inputs
+                    .iter()
+                    .zip(values)
+                    .map(|(name, v)| format!("{}={}", name, *v as u8))
+                    .collect();
+                write!(
+                    f,
+                    "not equivalent: {} gives {} and {}",
+                    assignment.join(" "),
+                    *lhs as u8,
+                    *rhs as u8
+                )
+            }
+        }
+    }
+}
+
+/// Checks whether two gate trees compute the same function, enumerating up to
+/// `TABLE_LIMIT` inputs.
+pub fn check(lhs: &CalcNode, rhs: &CalcNode) -> Result<Equivalence, String> {
+    let n = union_inputs(lhs, rhs).len();
+    let method = if n <= TABLE_LIMIT {
+        Method::TruthTable
+    } else {
+        Method::Bdd
+    };
+    check_with(lhs, rhs, method)
+}
+
+pub fn check_with(lhs: &CalcNode, rhs: &CalcNode, method: Method) -> Result<Equivalence, String> {
+    let inputs = union_inputs(lhs, rhs);
+    let mut evaluator = CalcEvaluator::default();
+    let mut values_at = |values: &[bool]| -> Result<(bool, bool), String> {
+        let assignment: HashMap<String, bool> =
+            inputs.iter().cloned().zip(values.iter().copied()).collect();
+        Ok((
+            evaluator.evaluate(lhs, &assignment)?,
+            evaluator.evaluate(rhs, &assignment)?,
+        ))
+    };
+
+    let n = inputs.len();
+    let mut counterexample = None;
+    match method {
+        Method::TruthTable => {
+            for m in 0..1u64 << n {
+                let values: Vec<bool> = (0..n).map(|i| m & (1 << (n - 1 - i)) != 0).collect();
+                let (l, r) = values_at(&values)?;
+                if l != r {
+                    counterexample = Some((values, l, r));
+                    break;
+                }
+            }
+        }
+        Method::Bdd => {
+            let mut bdd = Bdd::new(inputs.clone());
+            let l = bdd.build(lhs)?;
+            let r = bdd.build(rhs)?;
+            let diff = bdd.apply(&|x, y| x != y, l, r);
+            if let Some(values) = bdd.satisfy(diff) {
+                let (l, r) = values_at(&values)?;
+                counterexample = Some((values, l, r));
+            }
+        }
+    }
+    Ok(Equivalence {
+        inputs,
+        method,
+        counterexample,
+    })
+}
+
+fn union_inputs(lhs: &CalcNode, rhs: &CalcNode) -> Vec<String> {
+    let mut ans = inputs_of(lhs);
+    for f in inputs_of(rhs) {
+        if !ans.contains(&f) {
+            ans.push(f);
+        }
+    }
+    ans
+}
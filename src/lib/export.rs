@@ -0,0 +1,473 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fmt::Write;
+
+use super::{
+    netlist::{gate_value, Netlist, Signal},
+    node::{CalcFunctionData, CalcNode, CalcOperatorType},
+};
+
+/// Writes a netlist out in one file format.
+pub trait Exporter {
+    fn export(&self, netlist: &Netlist) -> Result<String, String>;
+}
+
+/// Format the verilog contexts emit their netlists in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Verilog,
+    Blif,
+    Json,
+    Dot,
+}
+
+impl ExportFormat {
+    pub fn exporter(&self) -> Box<dyn Exporter> {
+        match self {
+            ExportFormat::Verilog => Box::new(VerilogExporter),
+            ExportFormat::Blif => Box::new(BlifExporter),
+            ExportFormat::Json => Box::new(JsonExporter),
+            ExportFormat::Dot => Box::new(DotExporter),
+        }
+    }
+}
+
+impl TryFrom<&str> for ExportFormat {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "verilog" | "v" => Ok(ExportFormat::Verilog),
+            "blif" => Ok(ExportFormat::Blif),
+            "json" => Ok(ExportFormat::Json),
+            "dot" => Ok(ExportFormat::Dot),
+            _ => Err(format!("Unknown export format: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Verilog => write!(f, "verilog"),
+            ExportFormat::Blif => write!(f, "blif"),
+            ExportFormat::Json => write!(f, "json"),
+            ExportFormat::Dot => write!(f, "dot"),
+        }
+    }
+}
+
+pub struct VerilogExporter;
+
+impl Exporter for VerilogExporter {
+    fn export(&self, netlist: &Netlist) -> Result<String, String> {
+        Ok(netlist.to_verilog())
+    }
+}
+
+/// Berkeley Logic Interchange Format, as read by ABC and Yosys. Every gate is a `.names`
+/// table of the input combinations giving 1, with constant inputs folded in. Library cells
+/// are `.gate` instances and registers rising edge `.latch`es.
+pub struct BlifExporter;
+
+impl BlifExporter {
+    /// Net carrying `signal`. Constants get a net of their own, declared at the end.
+    fn net(netlist: &Netlist, signal: &Signal, constants: &mut BTreeSet<bool>) -> String {
+        match signal {
+            Signal::Constant(x) => {
+                constants.insert(x & 1 == 1);
+                format!("const_{}", x & 1)
+            }
+            x => netlist.name_of(x),
+        }
+    }
+}
+
+impl Exporter for BlifExporter {
+    fn export(&self, netlist: &Netlist) -> Result<String, String> {
+        let mut sout = String::new();
+        for note in &netlist.notes {
+            writeln!(sout, "# {}", note).expect("msg");
+        }
+        writeln!(sout, ".model {}", netlist.name).expect("msg");
+        writeln!(sout, ".inputs {}", netlist.inputs.join(" ")).expect("msg");
+        let outputs: Vec<&str> = netlist.outputs.iter().map(|f| f.0.as_str()).collect();
+        writeln!(sout, ".outputs {}", outputs.join(" ")).expect("msg");
+
+        let mut constants = BTreeSet::new();
+        for gate in &netlist.gates {
+            let wire = netlist.wire_name(gate.id);
+            if let Some(cell) = netlist.cell(&gate.kind) {
+                let pins: Vec<String> = cell
+                    .pins
+                    .iter()
+                    .zip(&gate.inputs)
+                    .map(|(pin, f)| format!("{}={}", pin, Self::net(netlist, f, &mut constants)))
+                    .collect();
+                writeln!(
+                    sout,
+                    ".gate {} {} {}={}",
+                    cell.name,
+                    pins.join(" "),
+                    cell.output,
+                    wire
+                )
+                .expect("msg");
+                continue;
+            }
+            let nets: Vec<&Signal> = gate
+                .inputs
+                .iter()
+                .filter(|f| !matches!(f, Signal::Constant(_)))
+                .collect();
+            let mut names: Vec<String> = nets.iter().map(|f| netlist.name_of(f)).collect();
+            names.push(wire);
+            writeln!(sout, ".names {}", names.join(" ")).expect("msg");
+            for m in 0..1u64 << nets.len() {
+                let row: Vec<u64> = (0..nets.len())
+                    .map(|i| (m >> (nets.len() - 1 - i)) & 1)
+                    .collect();
+                let mut bits = row.iter();
+                let ins: Vec<u64> = gate
+                    .inputs
+                    .iter()
+                    .map(|f| match f {
+                        Signal::Constant(x) => *x as u64,
+                        _ => *bits.next().unwrap(),
+                    })
+                    .collect();
+                if gate_value(&gate.kind, &ins)? & 1 == 1 {
+                    let row: String = row.iter().map(|f| f.to_string()).collect();
+                    match row.is_empty() {
+                        true => writeln!(sout, "1").expect("msg"),
+                        false => writeln!(sout, "{} 1", row).expect("msg"),
+                    }
+                }
+            }
+        }
+        for flop in &netlist.flops {
+            let d = Self::net(netlist, &flop.d, &mut constants);
+            let init = flop.initial() as u8;
+            match &flop.reset {
+                //a synchronous reset selects the reset value in front of the latch
+                Some((reset, value)) => {
+                    let next = format!("{}_next", flop.name);
+                    let rows = if *value { "1- 1\n01 1" } else { "01 1" };
+                    writeln!(sout, ".names {} {} {}\n{}", reset, d, next, rows).expect("msg");
+                    writeln!(
+                        sout,
+                        ".latch {} {} re {} {}",
+                        next, flop.name, flop.clock, init
+                    )
+                }
+                None => writeln!(
+                    sout,
+                    ".latch {} {} re {} {}",
+                    d, flop.name, flop.clock, init
+                ),
+            }
+            .expect("msg");
+        }
+        for output @ (name, signal) in &netlist.outputs {
+            if netlist.is_register_output(output) {
+                continue;
+            }
+            match signal {
+                Signal::Constant(x) if x & 1 == 1 => writeln!(sout, ".names {}\n1", name),
+                Signal::Constant(_) => writeln!(sout, ".names {}", name),
+                x => writeln!(sout, ".names {} {}\n1 1", netlist.name_of(x), name),
+            }
+            .expect("msg");
+        }
+        for x in constants {
+            match x {
+                true => writeln!(sout, ".names const_1\n1").expect("msg"),
+                false => writeln!(sout, ".names const_0").expect("msg"),
+            }
+        }
+        sout.push_str(".end");
+        Ok(sout)
+    }
+}
+
+/// JSON string literal of `text`.
+pub fn quote(text: &str) -> String {
+    let mut ans = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => ans.push_str("\\\""),
+            '\\' => ans.push_str("\\\\"),
+            '\n' => ans.push_str("\\n"),
+            c => ans.push(c),
+        }
+    }
+    ans.push('"');
+    ans
+}
+
+/// Pin of a library cell that `gate`'s `i`th input connects to.
+fn pin<'a>(netlist: &'a Netlist, kind: &str, i: usize) -> Option<&'a str> {
+    netlist.cell(kind).map(|f| f.pins[i].as_str())
+}
+
+/// Every signal read, and each output, as a node, with an edge from every signal to each gate,
+/// register or output reading it. Gate nodes are typed by gate kind or cell name.
+pub struct JsonExporter;
+
+impl Exporter for JsonExporter {
+    fn export(&self, netlist: &Netlist) -> Result<String, String> {
+        let list = |items: Vec<String>| format!("[{}]", items.join(", "));
+        let mut nodes: Vec<String> = netlist
+            .inputs
+            .iter()
+            .map(|f| format!("{{\"id\": {}, \"type\": \"input\"}}", quote(f)))
+            .collect();
+        let reads = netlist.gates.iter().flat_map(|f| &f.inputs);
+        let constants: BTreeSet<&Signal> = reads
+            .chain(netlist.outputs.iter().map(|f| &f.1))
+            .filter(|f| matches!(f, Signal::Constant(_)))
+            .collect();
+        for x in constants {
+            let id = quote(&x.to_string());
+            nodes.push(format!("{{\"id\": {}, \"type\": \"constant\"}}", id));
+        }
+        let mut edges = Vec::new();
+        for gate in &netlist.gates {
+            let id = quote(&netlist.wire_name(gate.id));
+            nodes.push(format!(
+                "{{\"id\": {}, \"type\": {}}}",
+                id,
+                quote(&gate.kind)
+            ));
+            for (i, input) in gate.inputs.iter().enumerate() {
+                let from = quote(&netlist.name_of(input));
+                edges.push(match pin(netlist, &gate.kind, i) {
+                    Some(p) => format!(
+                        "{{\"from\": {}, \"to\": {}, \"pin\": {}}}",
+                        from,
+                        id,
+                        quote(p)
+                    ),
+                    None => format!("{{\"from\": {}, \"to\": {}}}", from, id),
+                });
+            }
+        }
+        for flop in &netlist.flops {
+            let id = quote(&flop.name);
+            let reset = match &flop.reset {
+                Some((signal, value)) => {
+                    format!(
+                        ", \"reset\": {}, \"value\": {}",
+                        quote(signal),
+                        *value as u8
+                    )
+                }
+                None => String::new(),
+            };
+            nodes.push(format!(
+                "{{\"id\": {}, \"type\": \"register\", \"clock\": {}{}}}",
+                id,
+                quote(&flop.clock),
+                reset
+            ));
+            edges.push(format!(
+                "{{\"from\": {}, \"to\": {}, \"pin\": \"d\"}}",
+                quote(&netlist.name_of(&flop.d)),
+                id
+            ));
+        }
+        for output @ (name, signal) in &netlist.outputs {
+            if netlist.is_register_output(output) {
+                continue;
+            }
+            nodes.push(format!("{{\"id\": {}, \"type\": \"output\"}}", quote(name)));
+            edges.push(format!(
+                "{{\"from\": {}, \"to\": {}}}",
+                quote(&netlist.name_of(signal)),
+                quote(name)
+            ));
+        }
+
+        let fields = [
+            ("module", quote(&netlist.name)),
+            (
+                "notes",
+                list(netlist.notes.iter().map(|f| quote(f)).collect()),
+            ),
+            (
+                "inputs",
+                list(netlist.inputs.iter().map(|f| quote(f)).collect()),
+            ),
+            (
+                "outputs",
+                list(netlist.outputs.iter().map(|f| quote(&f.0)).collect()),
+            ),
+            ("nodes", format!("[\n    {}\n  ]", nodes.join(",\n    "))),
+            ("edges", format!("[\n    {}\n  ]", edges.join(",\n    "))),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(k, v)| format!("  {}: {}", quote(k), v))
+            .collect();
+        Ok(format!("{{\n{}\n}}", fields.join(",\n")))
+    }
+}
+
+/// Graphviz digraph flowing from the inputs on the left to the outputs on the right. Gates
+/// are labelled with their kind and wire, edges into library cells with the pin. Registers
+/// are drawn as boxes fed by their next value.
+pub struct DotExporter;
+
+impl Exporter for DotExporter {
+    fn export(&self, netlist: &Netlist) -> Result<String, String> {
+        let mut sout = String::new();
+        for note in &netlist.notes {
+            writeln!(sout, "// {}", note).expect("msg");
+        }
+        writeln!(sout, "digraph {} {{", quote(&netlist.name)).expect("msg");
+        writeln!(sout, "    rankdir=LR;").expect("msg");
+        for input in &netlist.inputs {
+            writeln!(sout, "    {} [shape=box];", quote(input)).expect("msg");
+        }
+        let mut edges = Vec::new();
+        let mut constants = BTreeSet::new();
+        for gate in &netlist.gates {
+            let wire = netlist.wire_name(gate.id);
+            writeln!(
+                sout,
+                "    {} [label={}];",
+                quote(&wire),
+                quote(&format!("{}\n{}", gate.kind, wire))
+            )
+            .expect("msg");
+            for (i, input) in gate.inputs.iter().enumerate() {
+                if let Signal::Constant(_) = input {
+                    constants.insert(input.to_string());
+                }
+                let label = match pin(netlist, &gate.kind, i) {
+                    Some(p) => format!(" [label={}]", quote(p)),
+                    None => String::new(),
+                };
+                edges.push(format!(
+                    "{} -> {}{};",
+                    quote(&netlist.name_of(input)),
+                    quote(&wire),
+                    label
+                ));
+            }
+        }
+        for flop in &netlist.flops {
+            if let Signal::Constant(_) = flop.d {
+                constants.insert(flop.d.to_string());
+            }
+            writeln!(sout, "    {} [shape=box3d];", quote(&flop.name)).expect("msg");
+            edges.push(format!(
+                "{} -> {};",
+                quote(&netlist.name_of(&flop.d)),
+                quote(&flop.name)
+            ));
+        }
+        for output @ (name, signal) in &netlist.outputs {
+            if netlist.is_register_output(output) {
+                continue;
+            }
+            if let Signal::Constant(_) = signal {
+                constants.insert(signal.to_string());
+            }
+            writeln!(sout, "    {} [shape=box];", quote(name)).expect("msg");
+            edges.push(format!(
+                "{} -> {};",
+                quote(&netlist.name_of(signal)),
+                quote(name)
+            ));
+        }
+        for x in constants {
+            writeln!(sout, "    {} [shape=plaintext];", quote(&x)).expect("msg");
+        }
+        for edge in edges {
+            writeln!(sout, "    {}", edge).expect("msg");
+        }
+        sout.push('}');
+        Ok(sout)
+    }
+}
+
+/// Graphviz digraph of an expression tree, children in parameter order. Nodes are labelled
+/// with the function name and operator, or the value, and functions with their id. Parsed
+/// functions have no id and are labelled with their operator alone. With `shared`, functions
+/// of the same id are one node, as they are one gate in a netlist.
+pub fn tree_to_dot(node: &CalcNode, shared: bool) -> String {
+    struct Builder {
+        shared: bool,
+        nodes: usize,
+        lines: Vec<String>,
+        ids: HashMap<usize, String>,
+    }
+
+    impl Builder {
+        fn visit(&mut self, node: &CalcNode) -> String {
+            //parsed functions have no gate id, they all carry 0 and are never the same node
+            let parsed = |x: &CalcFunctionData| x.id == 0 && x.operator.is_none();
+            if let (CalcNode::Function(x), true) = (node, self.shared) {
+                if let Some(name) = self.ids.get(&x.id).filter(|_| !parsed(x)) {
+                    return name.to_string();
+                }
+            }
+            let name = format!("n{}", self.nodes);
+            self.nodes += 1;
+            let (label, shape, children): (String, &str, Vec<&CalcNode>) = match node {
+                CalcNode::Function(x) if parsed(x) => {
+                    let label = match CalcOperatorType::from_binding(&x.name) {
+                        Some(op) => op.symbol().to_string(),
+                        None => x.name.to_string(),
+                    };
+                    (label, "ellipse", x.params.iter().collect())
+                }
+                CalcNode::Function(x) => {
+                    self.ids.insert(x.id, name.to_string());
+                    let label = match x.operator {
+                        Some(op) => format!("{} {}\n#{}", x.name, op.symbol(), x.id),
+                        None => format!("{}\n#{}", x.name, x.id),
+                    };
+                    (label, "ellipse", x.params.iter().collect())
+                }
+                CalcNode::UserFunction(x) => (
+                    format!("{}({})\n#{}", x.name, x.params.join(", "), x.id),
+                    "ellipse",
+                    vec![x.eval_tree.as_ref()],
+                ),
+                CalcNode::Text(x) => (x.to_string(), "box", vec![]),
+                CalcNode::Operator(x) => (x.symbol().to_string(), "diamond", vec![]),
+                CalcNode::SingleValue(x) => (x.to_string(), "plaintext", vec![]),
+                CalcNode::MultipleValue(x) => (format!("{:?}", x), "plaintext", vec![]),
+                CalcNode::NoValue => ("no value".to_string(), "plaintext", vec![]),
+            };
+            self.lines.push(format!(
+                "{} [label={}, shape={}];",
+                name,
+                quote(&label),
+                shape
+            ));
+            for child in children {
+                let child = self.visit(child);
+                self.lines.push(format!("{} -> {};", name, child));
+            }
+            name
+        }
+    }
+
+    let mut builder = Builder {
+        shared,
+        nodes: 0,
+        lines: Vec::new(),
+        ids: HashMap::new(),
+    };
+    builder.visit(node);
+    let mut sout = String::from("digraph expr {\n    ordering=out;\n");
+    for line in builder.lines {
+        writeln!(sout, "    {}", line).expect("msg");
+    }
+    sout.push('}');
+    sout
+}
@@ -0,0 +1,290 @@
+use std::fmt;
+
+const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerFormat {
+    #[default]
+    Decimal,
+    Hex,
+    Binary,
+    Octal,
+    Base(u32),
+}
+
+impl IntegerFormat {
+    pub fn radix(&self) -> u32 {
+        match self {
+            IntegerFormat::Decimal => 10,
+            IntegerFormat::Hex => 16,
+            IntegerFormat::Binary => 2,
+            IntegerFormat::Octal => 8,
+            IntegerFormat::Base(x) => *x,
+        }
+    }
+
+    pub fn render(&self, value: i64) -> String {
+        to_base(value, self.radix()).expect("Formats are parsed with a valid base")
+    }
+}
+
+impl TryFrom<&str> for IntegerFormat {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        match parts[..] {
+            ["dec"] | ["decimal"] => Ok(IntegerFormat::Decimal),
+            ["hex"] | ["hexadecimal"] => Ok(IntegerFormat::Hex),
+            ["bin"] | ["binary"] => Ok(IntegerFormat::Binary),
+            ["oct"] | ["octal"] => Ok(IntegerFormat::Octal),
+            ["base", x] => match x.parse::<u32>() {
+                Ok(r) if (2..=36).contains(&r) => Ok(IntegerFormat::Base(r)),
+                _ => Err(format!("Base must be between 2 and 36, got {}", x)),
+            },
+            _ => Err(format!("Unknown format: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for IntegerFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegerFormat::Decimal => write!(f, "dec"),
+            IntegerFormat::Hex => write!(f, "hex"),
+            IntegerFormat::Binary => write!(f, "bin"),
+            IntegerFormat::Octal => write!(f, "oct"),
+            IntegerFormat::Base(x) => write!(f, "base {}", x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// Fixed point, switching to scientific for very large or small magnitudes.
+    #[default]
+    Auto,
+    Fixed,
+    Scientific,
+    /// Scientific with the exponent a multiple of three.
+    Engineering,
+}
+
+impl TryFrom<&str> for Notation {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "auto" => Ok(Notation::Auto),
+            "fixed" => Ok(Notation::Fixed),
+            "sci" | "scientific" => Ok(Notation::Scientific),
+            "eng" | "engineering" => Ok(Notation::Engineering),
+            _ => Err(format!("Unknown format: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notation::Auto => write!(f, "auto"),
+            Notation::Fixed => write!(f, "fixed"),
+            Notation::Scientific => write!(f, "sci"),
+            Notation::Engineering => write!(f, "eng"),
+        }
+    }
+}
+
+/// Session wide settings for how results are rendered.
+#[derive(Debug, Clone)]
+pub struct NumberFormat {
+    pub base: IntegerFormat,
+    pub notation: Notation,
+    /// Significant digits, `None` for the shortest exact representation.
+    pub precision: Option<usize>,
+    pub separators: bool,
+    pub trim: bool,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            base: IntegerFormat::default(),
+            notation: Notation::default(),
+            precision: None,
+            separators: false,
+            trim: true,
+        }
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let on_off = |x: bool| if x { "on" } else { "off" };
+        write!(
+            f,
+            "format {}, {}; precision {}; separators {}; trim {}",
+            self.base,
+            self.notation,
+            self.precision.map_or("off".to_string(), |x| x.to_string()),
+            on_off(self.separators),
+            on_off(self.trim)
+        )
+    }
+}
+
+impl NumberFormat {
+    pub fn render(&self, value: f64) -> String {
+        if self.base != IntegerFormat::Decimal
+            && value.fract() == 0.0
+            && value.abs() < i64::MAX as f64
+        {
+            return self.base.render(value as i64);
+        }
+        if !value.is_finite() {
+            return format!("{}", value);
+        }
+
+        let (negative, digits, exponent) = decompose(value, self.precision);
+        let notation = match self.notation {
+            Notation::Auto => {
+                let limit = self.precision.unwrap_or(16).max(1) as i32;
+                if exponent < -5 || exponent >= limit {
+                    Notation::Scientific
+                } else {
+                    Notation::Fixed
+                }
+            }
+            x => x,
+        };
+        let (mut int_part, mut frac_part, exponent) = match notation {
+            Notation::Scientific => (
+                digits[..1].to_string(),
+                digits[1..].to_string(),
+                Some(exponent),
+            ),
+            Notation::Engineering => {
+                let eng = exponent.div_euclid(3) * 3;
+                let (i, f) = place(&digits, exponent - eng);
+                (i, f, Some(eng))
+            }
+            _ => {
+                let (i, f) = place(&digits, exponent);
+                (i, f, None)
+            }
+        };
+        if self.trim {
+            frac_part.truncate(frac_part.trim_end_matches('0').len());
+        }
+        if self.separators {
+            int_part = group_thousands(&int_part);
+        }
+
+        let mut ans = String::new();
+        if negative {
+            ans.push('-');
+        }
+        ans.push_str(&int_part);
+        if !frac_part.is_empty() {
+            ans.push('.');
+            ans.push_str(&frac_part);
+        }
+        if let Some(e) = exponent {
+            ans.push_str(&format!("e{}", e));
+        }
+        ans
+    }
+}
+
+/// Splits `value` into its sign, significant digits and decimal exponent, so that
+/// `value = d.ddd * 10^exponent`. Rounds to `precision` significant digits when given.
+fn decompose(value: f64, precision: Option<usize>) -> (bool, String, i32) {
+    let sci = match precision {
+        Some(p) => format!("{:.*e}", p.max(1) - 1, value.abs()),
+        None => format!("{:e}", value.abs()),
+    };
+    let (mantissa, exponent) = sci
+        .split_once('e')
+        .expect("scientific format has an exponent");
+    (
+        value.is_sign_negative() && value != 0.0,
+        mantissa.replace('.', ""),
+        exponent.parse().expect("exponent is an integer"),
+    )
+}
+
+/// Integer and fractional digits of `d.ddd * 10^exponent`.
+fn place(digits: &str, exponent: i32) -> (String, String) {
+    if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        return ("0".to_string(), format!("{}{}", zeros, digits));
+    }
+    let int_len = exponent as usize + 1;
+    if digits.len() <= int_len {
+        let zeros = "0".repeat(int_len - digits.len());
+        (format!("{}{}", digits, zeros), String::new())
+    } else {
+        (digits[..int_len].to_string(), digits[int_len..].to_string())
+    }
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut ans = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            ans.push(',');
+        }
+        ans.push(c);
+    }
+    ans
+}
+
+fn prefix(radix: u32) -> &'static str {
+    match radix {
+        16 => "0x",
+        8 => "0o",
+        2 => "0b",
+        _ => "",
+    }
+}
+
+/// Renders `value` in `radix`, with the usual `0x`/`0o`/`0b` prefix for hex, octal and binary.
+pub fn to_base(value: i64, radix: u32) -> Result<String, String> {
+    if !(2..=36).contains(&radix) {
+        return Err(format!("Base must be between 2 and 36, got {}", radix));
+    }
+    let mut magnitude = value.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(DIGITS[(magnitude % radix as u64) as usize]);
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    digits.reverse();
+    Ok(format!(
+        "{}{}{}",
+        if value < 0 { "-" } else { "" },
+        prefix(radix),
+        String::from_utf8(digits).expect("digits are ascii")
+    ))
+}
+
+/// Parses `0x`, `0o` and `0b` prefixed integer literals.
+pub fn parse_prefixed(text: &str) -> Option<f64> {
+    let lower = text.to_ascii_lowercase();
+    let (radix, digits) = if let Some(x) = lower.strip_prefix("0x") {
+        (16, x)
+    } else if let Some(x) = lower.strip_prefix("0o") {
+        (8, x)
+    } else if let Some(x) = lower.strip_prefix("0b") {
+        (2, x)
+    } else {
+        return None;
+    };
+    let digits = digits.replace('_', "");
+    u64::from_str_radix(&digits, radix)
+        .ok()
+        .map(|x| x as i64 as f64)
+}
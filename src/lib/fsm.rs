@@ -0,0 +1,424 @@
+use std::fmt;
+use std::fmt::Write;
+
+use super::{
+    bus::bit_name,
+    export::quote,
+    funcs::check_verilog_operators,
+    node::{CalcFunctionData, CalcNode},
+    seq::is_name,
+};
+
+/// How the states of a machine are encoded in its state register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsmEncoding {
+    Binary,
+    OneHot,
+    Gray,
+}
+
+impl FsmEncoding {
+    /// Bits of the state register for `n` states.
+    pub fn width(&self, n: usize) -> usize {
+        match self {
+            FsmEncoding::OneHot => n.max(1),
+            _ => (usize::BITS - n.saturating_sub(1).leading_zeros()).max(1) as usize,
+        }
+    }
+
+    /// Code of the `i`th state.
+    pub fn code(&self, i: usize) -> u64 {
+        match self {
+            FsmEncoding::Binary => i as u64,
+            FsmEncoding::OneHot => 1u64.checked_shl(i as u32).unwrap_or(0),
+            FsmEncoding::Gray => (i ^ (i >> 1)) as u64,
+        }
+    }
+}
+
+impl TryFrom<&str> for FsmEncoding {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "binary" => Ok(FsmEncoding::Binary),
+            "onehot" => Ok(FsmEncoding::OneHot),
+            "gray" => Ok(FsmEncoding::Gray),
+            _ => Err(format!("Expected binary, onehot or gray, got {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for FsmEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsmEncoding::Binary => write!(f, "binary"),
+            FsmEncoding::OneHot => write!(f, "onehot"),
+            FsmEncoding::Gray => write!(f, "gray"),
+        }
+    }
+}
+
+/// Outputs set to 1, as written after a state or a transition: `y = 1, z = 0`.
+fn parse_outputs(text: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut ans = Vec::new();
+    for assignment in text.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("Expected an output like y = 1, got {}", assignment))?;
+        let value = match value.trim() {
+            "0" => false,
+            "1" => true,
+            x => return Err(format!("Outputs are 0 or 1, got {}", x)),
+        };
+        ans.push((word(name)?, value));
+    }
+    Ok(ans)
+}
+
+/// `text` as a single name.
+fn word(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return Err(format!("Expected a name, got {}", text));
+    }
+    Ok(text.to_string())
+}
+
+/// A state and its Moore outputs.
+#[derive(Debug, Clone)]
+pub struct State {
+    pub name: String,
+    pub outputs: Vec<(String, bool)>,
+}
+
+/// A transition, taken on a clock edge where its guard holds and no earlier transition out of
+/// the same state is taken. It sets its Mealy outputs while it would be taken.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub from: String,
+    pub to: String,
+    /// The guard as written, and parsed. No guard always holds.
+    pub guard: Option<(String, CalcNode)>,
+    pub outputs: Vec<(String, bool)>,
+}
+
+/// Next value of every state bit and the value of every output, as expression trees over the
+/// inputs and the state bits.
+pub struct FsmLogic {
+    pub next: Vec<CalcNode>,
+    pub outputs: Vec<(String, CalcNode)>,
+}
+
+/// Machine described with `fsm light on clk reset rst`, then `state red stop = 1` for each
+/// state and `trans red -> green if go / honk = 1` for each transition. The first state is
+/// the one the reset loads, a state with no transition taken stays where it is, and outputs
+/// not set are 0.
+#[derive(Debug, Clone)]
+pub struct Fsm {
+    pub name: String,
+    pub clock: String,
+    pub reset: String,
+    pub states: Vec<State>,
+    pub transitions: Vec<Transition>,
+}
+
+/// Name of the state register.
+pub const STATE: &str = "state";
+
+pub const NO_FSM: &str = "No fsm declared, start one with fsm <name> on <clock> reset <signal>";
+
+impl Fsm {
+    /// Whether `rest`, a line without its leading `keyword`, is shaped like a line of the
+    /// machine description: `light on clk reset rst`, `red stop = 1` or `red -> green`.
+    /// Other lines starting with the keywords are expressions, e.g. `state & go`.
+    pub fn is_line(keyword: &str, rest: &str) -> bool {
+        let first = |x: &str| x.split_whitespace().next().is_some_and(is_name);
+        match keyword {
+            "fsm" => rest
+                .split_once(" on ")
+                .is_some_and(|(name, clocking)| is_name(name) && clocking.contains(" reset ")),
+            "state" => first(rest),
+            "trans" => rest
+                .split_once("->")
+                .is_some_and(|(from, to)| is_name(from) && first(to)),
+            _ => false,
+        }
+    }
+
+    /// Parses `light on clk reset rst`, a declaration without its leading `fsm`.
+    pub fn declare(decl: &str) -> Result<Fsm, String> {
+        const USAGE: &str = "Usage: fsm <name> on <clock> reset <signal>";
+        let (name, clocking) = decl.split_once(" on ").ok_or_else(|| USAGE.to_string())?;
+        let (clock, reset) = clocking
+            .split_once(" reset ")
+            .ok_or_else(|| USAGE.to_string())?;
+        Ok(Fsm {
+            name: word(name)?,
+            clock: word(clock)?,
+            reset: word(reset)?,
+            states: Vec::new(),
+            transitions: Vec::new(),
+        })
+    }
+
+    /// Adds the state of `red stop = 1, go = 0`, a line without its leading `state`.
+    /// Redeclaring a state replaces its outputs.
+    pub fn add_state(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        let (name, outputs) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let state = State {
+            name: word(name)?,
+            outputs: parse_outputs(outputs)?,
+        };
+        match self.states.iter_mut().find(|f| f.name == state.name) {
+            Some(x) => *x = state,
+            None => self.states.push(state),
+        }
+        Ok(())
+    }
+
+    /// Adds the transition of `red -> green if go / honk = 1`, a line without its leading
+    /// `trans`.
+    pub fn add_transition(&mut self, line: &str) -> Result<(), String> {
+        let (from, rest) = line
+            .split_once("->")
+            .ok_or("Usage: trans <state> -> <state> [if <guard>] [/ <outputs>]")?;
+        let (rest, outputs) = rest.split_once('/').unwrap_or((rest, ""));
+        let (to, guard) = match rest.split_once(" if ") {
+            Some((to, guard)) => {
+                let guard = guard.trim();
+                let tree = crate::parse(guard)
+                    .ok_or_else(|| format!("Expected a guard expression, got {}", guard))?;
+                check_verilog_operators(&tree)?;
+                (to, Some((guard.to_string(), tree)))
+            }
+            None => (rest, None),
+        };
+        self.transitions.push(Transition {
+            from: word(from)?,
+            to: word(to)?,
+            guard,
+            outputs: parse_outputs(outputs)?,
+        });
+        Ok(())
+    }
+
+    fn state_index(&self, name: &str) -> Result<usize, String> {
+        self.states
+            .iter()
+            .position(|f| f.name == name)
+            .ok_or_else(|| format!("No state named {} in {}", name, self.name))
+    }
+
+    /// Outputs in order of first use, with whether the states (Moore) or the transitions
+    /// (Mealy) set them.
+    fn output_names(&self) -> Result<Vec<(String, bool)>, String> {
+        let mut ans: Vec<(String, bool)> = Vec::new();
+        let moore = self
+            .states
+            .iter()
+            .flat_map(|f| &f.outputs)
+            .map(|f| (f, true));
+        let mealy = self.transitions.iter().flat_map(|f| &f.outputs);
+        for ((name, _), is_moore) in moore.chain(mealy.map(|f| (f, false))) {
+            match ans.iter().find(|f| f.0 == *name) {
+                Some(x) if x.1 != is_moore => {
+                    return Err(format!("{} is set by both states and transitions", name))
+                }
+                Some(_) => {}
+                None => ans.push((name.to_string(), is_moore)),
+            }
+        }
+        Ok(ans)
+    }
+
+    /// Register code of every state, e.g. `red 00, green 01 (binary)`.
+    pub fn codes(&self, encoding: FsmEncoding) -> String {
+        let width = encoding.width(self.states.len());
+        let codes: Vec<String> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(i, f)| format!("{} {:0w$b}", f.name, encoding.code(i), w = width))
+            .collect();
+        format!("{} ({})", codes.join(", "), encoding)
+    }
+
+    /// The machine as gates over the inputs and the bits of the state register, not yet
+    /// lowered to the gates of any context.
+    pub fn logic(&self, encoding: FsmEncoding) -> Result<FsmLogic, String> {
+        if self.states.is_empty() {
+            return Err(format!("{} has no states", self.name));
+        }
+        if encoding.width(self.states.len()) > 64 {
+            return Err(format!(
+                "{} has too many states for {}",
+                self.name, encoding
+            ));
+        }
+        let targets = self
+            .transitions
+            .iter()
+            .map(|f| self.state_index(&f.from).and(self.state_index(&f.to)))
+            .collect::<Result<Vec<usize>, String>>()?;
+        let width = encoding.width(self.states.len());
+        let bit = |i: usize| CalcNode::Text(bit_name(STATE, i));
+        let is_in = |i: usize| match encoding {
+            FsmEncoding::OneHot => bit(i),
+            _ => all((0..width)
+                .map(|b| match encoding.code(i) >> b & 1 {
+                    1 => bit(b),
+                    _ => call("not", vec![bit(b)]),
+                })
+                .collect()),
+        };
+
+        //when each transition is taken, and when each state is left as it is
+        let mut taken: Vec<Option<CalcNode>> = vec![None; self.transitions.len()];
+        let mut stays = Vec::new();
+        for (i, state) in self.states.iter().enumerate() {
+            let mut earlier: Vec<CalcNode> = Vec::new();
+            let mut always = false;
+            for (t, transition) in self.transitions.iter().enumerate() {
+                if transition.from != state.name || always {
+                    continue;
+                }
+                let mut terms = vec![is_in(i)];
+                terms.extend(earlier.iter().map(negate));
+                match &transition.guard {
+                    Some((_, guard)) => {
+                        terms.push(guard.clone());
+                        earlier.push(guard.clone());
+                    }
+                    None => always = true,
+                }
+                taken[t] = Some(all(terms));
+            }
+            if !always {
+                let mut terms = vec![is_in(i)];
+                terms.extend(earlier.iter().map(negate));
+                stays.push((i, all(terms)));
+            }
+        }
+
+        let mut next = Vec::new();
+        for b in 0..width {
+            let moves = targets
+                .iter()
+                .zip(&taken)
+                .filter(|(to, _)| encoding.code(**to) >> b & 1 == 1)
+                .filter_map(|(_, c)| c.clone());
+            let stay = stays
+                .iter()
+                .filter(|(i, _)| encoding.code(*i) >> b & 1 == 1)
+                .map(|f| f.1.clone());
+            next.push(any(moves.chain(stay).collect()));
+        }
+
+        let mut outputs = Vec::new();
+        for (name, is_moore) in self.output_names()? {
+            let set = |outputs: &[(String, bool)]| outputs.contains(&(name.to_string(), true));
+            let terms: Vec<CalcNode> = if is_moore {
+                let states = self.states.iter().enumerate();
+                states
+                    .filter(|(_, f)| set(&f.outputs))
+                    .map(|(i, _)| is_in(i))
+                    .collect()
+            } else {
+                let transitions = self.transitions.iter().zip(&taken);
+                transitions
+                    .filter(|(t, _)| set(&t.outputs))
+                    .filter_map(|(_, c)| c.clone())
+                    .collect()
+            };
+            outputs.push((name, any(terms)));
+        }
+        Ok(FsmLogic { next, outputs })
+    }
+
+    /// Graphviz state diagram. The reset state is drawn with a double circle, states are
+    /// labelled with their code and Moore outputs and transitions with their guard and Mealy
+    /// outputs.
+    pub fn to_dot(&self, encoding: FsmEncoding) -> String {
+        let ones = |outputs: &[(String, bool)]| {
+            let ones: Vec<&str> = outputs
+                .iter()
+                .filter(|f| f.1)
+                .map(|f| f.0.as_str())
+                .collect();
+            ones.join(", ")
+        };
+        let width = encoding.width(self.states.len());
+        let mut sout = String::new();
+        writeln!(sout, "digraph {} {{", quote(&self.name)).expect("msg");
+        writeln!(sout, "    rankdir=LR;").expect("msg");
+        for (i, state) in self.states.iter().enumerate() {
+            let mut label = format!("{}\n{:0w$b}", state.name, encoding.code(i), w = width);
+            let outputs = ones(&state.outputs);
+            if !outputs.is_empty() {
+                write!(label, "\n{}", outputs).expect("msg");
+            }
+            let shape = if i == 0 { "doublecircle" } else { "circle" };
+            writeln!(
+                sout,
+                "    {} [shape={}, label={}];",
+                quote(&state.name),
+                shape,
+                quote(&label)
+            )
+            .expect("msg");
+        }
+        for transition in &self.transitions {
+            let mut label = match &transition.guard {
+                Some((text, _)) => text.to_string(),
+                None => String::new(),
+            };
+            let outputs = ones(&transition.outputs);
+            if !outputs.is_empty() {
+                write!(label, " / {}", outputs).expect("msg");
+            }
+            writeln!(
+                sout,
+                "    {} -> {} [label={}];",
+                quote(&transition.from),
+                quote(&transition.to),
+                quote(label.trim())
+            )
+            .expect("msg");
+        }
+        sout.push('}');
+        sout
+    }
+}
+
+fn call(name: &str, params: Vec<CalcNode>) -> CalcNode {
+    CalcNode::Function(CalcFunctionData {
+        params,
+        ..CalcFunctionData::new(name)
+    })
+}
+
+/// Inverse of `x`, without stacking a second inverter on an inverted guard.
+fn negate(x: &CalcNode) -> CalcNode {
+    match x {
+        CalcNode::Function(f) if f.name == "not" && f.params.len() == 1 => f.params[0].clone(),
+        _ => call("not", vec![x.clone()]),
+    }
+}
+
+/// And of `terms`, 1 when there are none.
+fn all(terms: Vec<CalcNode>) -> CalcNode {
+    terms
+        .into_iter()
+        .reduce(|x, y| call("and", vec![x, y]))
+        .unwrap_or(CalcNode::SingleValue(1.0))
+}
+
+/// Or of `terms`, 0 when there are none.
+fn any(terms: Vec<CalcNode>) -> CalcNode {
+    terms
+        .into_iter()
+        .reduce(|x, y| call("or", vec![x, y]))
+        .unwrap_or(CalcNode::SingleValue(0.0))
+}
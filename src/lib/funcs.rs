@@ -5,6 +5,7 @@ use std::sync::Arc;
 use crate::lib::EvalFunction;
 use crate::{CalcFunctionData, CalcNode};
 
+use super::format::to_base;
 use super::node::CalcOperatorType;
 
 pub fn bound_check(re: &Vec<CalcNode>, size: usize) {
@@ -63,6 +64,296 @@ pub fn binary_fn__(
     });
 }
 
+/// Gamma function via the Lanczos approximation (g = 7, n = 9).
+pub fn gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        //reflection formula
+        return std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let mut a = COEFFICIENTS[0];
+    let t = x + G + 0.5;
+    for (i, c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        a += c / (x + i as f64);
+    }
+    (2.0 * std::f64::consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * a
+}
+
+/// Exact product for non-negative integers, gamma(x + 1) otherwise. Anything past 170!
+/// overflows an f64, so it is infinite without multiplying it out.
+pub fn factorial(x: f64) -> f64 {
+    if x.fract() == 0.0 {
+        if x < 0.0 {
+            return f64::NAN;
+        }
+        if x > 170.0 {
+            return f64::INFINITY;
+        }
+        return (1..=(x as u64)).fold(1.0, |acc, i| acc * i as f64);
+    }
+    gamma(x + 1.0)
+}
+
+pub fn shift_left(x: f64, y: f64) -> f64 {
+    (x as u64).checked_shl(y as u32).unwrap_or(0) as f64
+}
+
+pub fn shift_right(x: f64, y: f64) -> f64 {
+    (x as u64).checked_shr(y as u32).unwrap_or(0) as f64
+}
+
+pub fn arithmetic_shift_right(x: f64, y: f64) -> f64 {
+    let x = x as i64;
+    x.checked_shr(y as u32)
+        .unwrap_or(if x < 0 { -1 } else { 0 }) as f64
+}
+
+/// Rotates the low `width` bits of `x` by `n` places.
+pub fn rotate(x: f64, n: f64, width: f64, left: bool) -> f64 {
+    let width = (width as u32).clamp(1, 64);
+    let mask = u64::MAX >> (64 - width);
+    let value = (x as u64) & mask;
+    let n = (n as i64).rem_euclid(width as i64) as u32;
+    if n == 0 {
+        return value as f64;
+    }
+    let n = if left { n } else { width - n };
+    (((value << n) | (value >> (width - n))) & mask) as f64
+}
+
+/// `rotl(x, n)` / `rotr(x, n)` rotate a 64 bit register, `rotl(x, n, width)` a narrower one.
+pub fn rotate_fn(nm: &str, re: Vec<CalcNode>, left: bool, counter: Arc<AtomicUsize>) -> CalcNode {
+    if re.len() != 2 {
+        bound_check(&re, 3);
+    }
+    let values: Vec<f64> = re
+        .iter()
+        .filter_map(|f| match f {
+            CalcNode::SingleValue(x) => Some(*x),
+            _ => None,
+        })
+        .collect();
+    if values.len() == re.len() {
+        let width = values.get(2).copied().unwrap_or(64.0);
+        return CalcNode::SingleValue(rotate(values[0], values[1], width, left));
+    }
+    CalcNode::Function(CalcFunctionData {
+        name: nm.to_string(),
+        params: re,
+        operator: None,
+        brackets: false,
+        id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+    })
+}
+
+/// `concat` of the Verilog contexts, kept as a node for the bus pass to expand.
+fn insert_concat(ur: &mut HashMap<String, EvalFunction>) {
+    ur.insert(
+        "concat".to_string(),
+        Box::new(|t, counter: Arc<AtomicUsize>| {
+            CalcNode::Function(CalcFunctionData {
+                name: "concat".to_string(),
+                brackets: false,
+                operator: None,
+                params: t,
+                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            })
+        }) as Box<_>,
+    );
+}
+
+/// `prev` of the Verilog contexts, kept as a node for the netlist to turn into a register.
+fn insert_prev(ur: &mut HashMap<String, EvalFunction>) {
+    ur.insert(
+        "prev".to_string(),
+        Box::new(|t, counter: Arc<AtomicUsize>| {
+            bound_check(&t, 1);
+            CalcNode::Function(CalcFunctionData {
+                name: "prev".to_string(),
+                brackets: false,
+                operator: None,
+                params: t,
+                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            })
+        }) as Box<_>,
+    );
+}
+
+/// Shifts are not gates, so the calculator and every Verilog context register them the same
+/// way.
+fn insert_shifts(ur: &mut HashMap<String, EvalFunction>) {
+    ur.insert(
+        "shl".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn("shl", t, &shift_left, CalcOperatorType::ShiftLeft, counter)
+        }) as Box<_>,
+    );
+
+    ur.insert(
+        "shr".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn(
+                "shr",
+                t,
+                &shift_right,
+                CalcOperatorType::ShiftRight,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
+    ur.insert(
+        "ashr".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn(
+                "ashr",
+                t,
+                &arithmetic_shift_right,
+                CalcOperatorType::ArithmeticShiftRight,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
+    ur.insert(
+        "rotl".to_string(),
+        Box::new(|t, counter| rotate_fn("rotl", t, true, counter)) as Box<_>,
+    );
+
+    ur.insert(
+        "rotr".to_string(),
+        Box::new(|t, counter| rotate_fn("rotr", t, false, counter)) as Box<_>,
+    );
+}
+
+/// A comparison or logical operator. The calculator gives 1 where it holds and 0 elsewhere; over
+/// one bit signals it is `gate` of the operands, those marked in `invert` inverted first, so
+/// `a < b` is `~a & b`.
+struct Relation {
+    name: &'static str,
+    operator: CalcOperatorType,
+    holds: fn(f64, f64) -> bool,
+    gate: &'static str,
+    invert: [bool; 2],
+}
+
+fn relations() -> [Relation; 9] {
+    use CalcOperatorType::*;
+    let relation = |name, operator, holds, gate, invert| Relation {
+        name,
+        operator,
+        holds,
+        gate,
+        invert,
+    };
+    [
+        relation("less", Less, |x, y| x < y, "and", [true, false]),
+        relation("lessequal", LessEquals, |x, y| x <= y, "or", [true, false]),
+        relation("greater", Greater, |x, y| x > y, "and", [false, true]),
+        relation(
+            "greaterequal",
+            GreaterEquals,
+            |x, y| x >= y,
+            "or",
+            [false, true],
+        ),
+        relation("equal", EqualsEquals, |x, y| x == y, "xnor", [false, false]),
+        relation("notequal", NotEquals, |x, y| x != y, "xor", [false, false]),
+        relation(
+            "land",
+            LogicalAnd,
+            |x, y| x != 0.0 && y != 0.0,
+            "and",
+            [false, false],
+        ),
+        relation(
+            "lor",
+            LogicalOr,
+            |x, y| x != 0.0 || y != 0.0,
+            "or",
+            [false, false],
+        ),
+        relation(
+            "implies",
+            Arrow,
+            |x, y| x == 0.0 || y != 0.0,
+            "or",
+            [true, false],
+        ),
+    ]
+}
+
+/// `Err` when `node` uses `**`. The verilog contexts have no gates for it: Verilog reads it
+/// as exponentiation, not as the `^` the calculator takes it for.
+pub fn check_verilog_operators(node: &CalcNode) -> Result<(), String> {
+    match node {
+        CalcNode::Function(x) if x.name == "exponent" => {
+            Err("** is exponentiation in Verilog and has no gates, use ^ for xor".to_string())
+        }
+        CalcNode::Function(x) => x.params.iter().try_for_each(check_verilog_operators),
+        CalcNode::UserFunction(x) => check_verilog_operators(&x.eval_tree),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `op` compares its operands rather than working bit by bit.
+pub fn is_relation(op: CalcOperatorType) -> bool {
+    relations().iter().any(|f| f.operator == op)
+}
+
+/// The relations of the Verilog contexts, built from plain gates or, given `universal`, from
+/// nand or nor gates only. The last gate keeps the operator, so the bus pass can tell it is a
+/// relation and not a bitwise gate.
+fn insert_relations(ur: &mut HashMap<String, EvalFunction>, universal: Option<&'static str>) {
+    for relation in relations() {
+        ur.insert(
+            relation.name.to_string(),
+            Box::new(move |t: Vec<CalcNode>, counter: Arc<AtomicUsize>| {
+                bound_check(&t, 2);
+                if let [CalcNode::SingleValue(x), CalcNode::SingleValue(y)] = t[..] {
+                    return CalcNode::SingleValue((relation.holds)(x, y) as u8 as f64);
+                }
+                let lower = |nm: &str, params: Vec<CalcNode>| match universal {
+                    Some(u) => lower_universal(u, nm, params, &counter),
+                    None => gate(nm, params, &counter),
+                };
+                let params = t
+                    .into_iter()
+                    .zip(relation.invert)
+                    .map(|(x, invert)| match (x, invert) {
+                        (CalcNode::SingleValue(x), true) => {
+                            CalcNode::SingleValue((x == 0.0) as u8 as f64)
+                        }
+                        (x, true) => lower("not", vec![x]),
+                        (x, false) => x,
+                    })
+                    .collect();
+                let mut ans = lower(relation.gate, params);
+                if let CalcNode::Function(x) = &mut ans {
+                    x.operator = Some(relation.operator);
+                }
+                ans
+            }) as Box<_>,
+        );
+    }
+}
+
 pub fn assemble_map_calc() -> HashMap<String, EvalFunction> {
     let mut ur: HashMap<String, EvalFunction> = HashMap::new();
 
@@ -127,6 +418,20 @@ pub fn assemble_map_calc() -> HashMap<String, EvalFunction> {
         }) as Box<_>,
     );
 
+    ur.insert(
+        "exponent".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn(
+                "exponent",
+                t,
+                &|x, y| x.powf(y),
+                CalcOperatorType::DoubleAsterisk,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
     ur.insert(
         "modulus".to_string(),
         Box::new(|t, counter| {
@@ -141,6 +446,34 @@ pub fn assemble_map_calc() -> HashMap<String, EvalFunction> {
         }) as Box<_>,
     );
 
+    ur.insert(
+        "factorial".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 1);
+            unary_fn(
+                "factorial",
+                t,
+                &factorial,
+                CalcOperatorType::Exclamation,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
+    ur.insert(
+        "percent".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 1);
+            unary_fn(
+                "percent",
+                t,
+                &|x| x / 100.0,
+                CalcOperatorType::Percent,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
     ur.insert(
         "and".to_string(),
         Box::new(|t, counter| {
@@ -197,6 +530,84 @@ pub fn assemble_map_calc() -> HashMap<String, EvalFunction> {
         }) as Box<_>,
     );
 
+    ur.insert(
+        "nand".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn__(
+                "nand",
+                t,
+                &|x, y| !((x as u64) & (y as u64)) as f64,
+                None,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
+    ur.insert(
+        "nor".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn__(
+                "nor",
+                t,
+                &|x, y| !((x as u64) | (y as u64)) as f64,
+                None,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
+    ur.insert(
+        "xnor".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn__(
+                "xnor",
+                t,
+                &|x, y| !((x as u64) ^ (y as u64)) as f64,
+                None,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
+    insert_shifts(&mut ur);
+
+    ur.insert(
+        "to_base".to_string(),
+        Box::new(|t: Vec<CalcNode>, counter: Arc<AtomicUsize>| {
+            bound_check(&t, 2);
+            if let (CalcNode::SingleValue(x), CalcNode::SingleValue(y)) = (&t[0], &t[1]) {
+                //the error is the result, for the caller to show like any other
+                return CalcNode::Text(to_base(*x as i64, *y as u32).unwrap_or_else(|e| e));
+            }
+            CalcNode::Function(CalcFunctionData {
+                name: "to_base".to_string(),
+                params: t,
+                operator: None,
+                brackets: false,
+                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            })
+        }) as Box<_>,
+    );
+
+    for relation in relations() {
+        ur.insert(
+            relation.name.to_string(),
+            Box::new(move |t, counter| {
+                bound_check(&t, 2);
+                binary_fn(
+                    relation.name,
+                    t,
+                    &|x, y| (relation.holds)(x, y) as u8 as f64,
+                    relation.operator,
+                    counter,
+                )
+            }) as Box<_>,
+        );
+    }
+
     return ur;
 }
 
@@ -287,6 +698,20 @@ pub fn assemble_map_veri() -> HashMap<String, EvalFunction> {
         }) as Box<_>,
     );
 
+    ur.insert(
+        "power".to_string(),
+        Box::new(|t, counter| {
+            bound_check(&t, 2);
+            binary_fn(
+                "xor",
+                t,
+                &|x, y| ((x as u64) ^ (y as u64)) as f64,
+                CalcOperatorType::Caret,
+                counter,
+            )
+        }) as Box<_>,
+    );
+
     ur.insert(
         "xor".to_string(),
         Box::new(|t, counter| {
@@ -343,293 +768,95 @@ pub fn assemble_map_veri() -> HashMap<String, EvalFunction> {
         }) as Box<_>,
     );
 
+    insert_shifts(&mut ur);
+    insert_relations(&mut ur, None);
+    insert_concat(&mut ur);
+    insert_prev(&mut ur);
+
     return ur;
 }
 
-pub fn assemble_map_veri_nand() -> HashMap<String, EvalFunction> {
-    let mut ur: HashMap<String, EvalFunction> = HashMap::new();
-
-    ur.insert(
-        "multiply".to_string(),
-        Box::new(|t, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 2);
-            let first = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: t,
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            let first_pt_2 = first.clone();
-            let second = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first, first_pt_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            second
-        }) as Box<_>,
-    );
-
-    ur.insert(
-        "and".to_string(),
-        Box::new(|t, counter: Arc<AtomicUsize>| {
-            let first = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: t,
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            let first_pt_2 = first.clone();
-            let second = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first, first_pt_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            second
-        }) as Box<_>,
-    );
-
-    ur.insert(
-        "add".to_string(),
-        Box::new(|t: Vec<_>, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 2);
-
-            let first_param = t[0].clone();
-            let first_param_2 = t[0].clone();
-
-            let first_not = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_param, first_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-
-            let second_param = t[1].clone();
-            let second_param_2 = t[1].clone();
-
-            let second_not = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![second_param, second_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-
-            let third_nand = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_not, second_not],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            third_nand
-        }) as Box<_>,
-    );
-
-    ur.insert(
-        "or".to_string(),
-        Box::new(|t: Vec<_>, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 2);
-            let first_param = t[0].clone();
-            let first_param_2 = t[0].clone();
-
-            let first_not = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_param, first_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-
-            let second_param = t[1].clone();
-            let second_param_2 = t[1].clone();
-
-            let second_not = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![second_param, second_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-
-            let third_nand = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_not, second_not],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            third_nand
-        }) as Box<_>,
-    );
-
-    ur.insert(
-        "not".to_string(),
-        Box::new(|t, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 1);
-            let yy = t[0].clone();
-            let yy_pt = t[0].clone();
-            let second = CalcNode::Function(CalcFunctionData {
-                name: "nand".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![yy, yy_pt],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            second
-            // unary_fn("not", t, &|x| (!(x as u64)) as f64, CalcOperatorType::Tild, width, depth)
-        }) as Box<_>,
-    );
+/// Gate `nm` over `params`, with no operator to print it with.
+fn gate(nm: &str, params: Vec<CalcNode>, counter: &AtomicUsize) -> CalcNode {
+    CalcNode::Function(CalcFunctionData {
+        name: nm.to_string(),
+        brackets: false,
+        operator: None,
+        params,
+        id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+    })
+}
 
-    return ur;
+/// Lowers the Verilog gate `nm` onto `universal`, which is either "nand" or "nor". A shared
+/// operand is cloned rather than rebuilt, so both uses keep the id of the same gate.
+fn lower_universal(universal: &str, nm: &str, t: Vec<CalcNode>, counter: &AtomicUsize) -> CalcNode {
+    let is_nand = universal == "nand";
+    let g = |x: CalcNode, y: CalcNode| gate(universal, vec![x, y], counter);
+    let inv = |x: CalcNode| gate(universal, vec![x.clone(), x], counter);
+    if nm == "not" {
+        bound_check(&t, 1);
+        return inv(t[0].clone());
+    }
+    bound_check(&t, 2);
+    let (a, b) = (t[0].clone(), t[1].clone());
+    match nm {
+        _ if nm == universal => g(a, b),
+        //and for nand is the inverted gate, for nor it is De Morgan's, or the other way round
+        "and" | "or" if (nm == "and") == is_nand => inv(g(a, b)),
+        "and" | "or" => g(inv(a), inv(b)),
+        "nand" => inv(lower_universal(universal, "and", t, counter)),
+        "nor" => inv(lower_universal(universal, "or", t, counter)),
+        "xor" | "xnor" => {
+            //four gate parity, xor out of nands and xnor out of nors
+            let m = g(a.clone(), b.clone());
+            let parity = g(g(a, m.clone()), g(b, m));
+            if (nm == "xor") == is_nand {
+                parity
+            } else {
+                inv(parity)
+            }
+        }
+        _ => panic!("No {} lowering for {}", universal, nm),
+    }
 }
 
-pub fn assemble_map_veri_nor() -> HashMap<String, EvalFunction> {
+/// Verilog operators built from `universal` gates only, keyed like `assemble_map_veri`.
+fn assemble_map_universal(universal: &'static str) -> HashMap<String, EvalFunction> {
     let mut ur: HashMap<String, EvalFunction> = HashMap::new();
 
-    ur.insert(
-        "add".to_string(),
-        Box::new(|t, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 2);
-            let first = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: t,
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            let first_pt_2 = first.clone();
-            let second = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first, first_pt_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            second
-        }) as Box<_>,
-    );
-
-    ur.insert(
-        "or".to_string(),
-        Box::new(|t, counter: Arc<AtomicUsize>| {
-            let first = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: t,
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            let first_pt_2 = first.clone();
-            let second = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first, first_pt_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            second
-        }) as Box<_>,
-    );
-
-    ur.insert(
-        "multiply".to_string(),
-        Box::new(|t: Vec<_>, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 2);
-
-            let first_param = t[0].clone();
-            let first_param_2 = t[0].clone();
-
-            let first_not = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_param, first_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-
-            let second_param = t[1].clone();
-            let second_param_2 = t[1].clone();
-
-            let second_not = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![second_param, second_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-
-            let third_nand = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_not, second_not],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            third_nand
-        }) as Box<_>,
-    );
-
-    ur.insert(
-        "and".to_string(),
-        Box::new(|t: Vec<_>, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 2);
-            let first_param = t[0].clone();
-            let first_param_2 = t[0].clone();
-
-            let first_not = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_param, first_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
+    for (key, nm) in [
+        ("add", "or"),
+        ("or", "or"),
+        ("multiply", "and"),
+        ("and", "and"),
+        ("not", "not"),
+        ("power", "xor"),
+        ("inverse", "xor"),
+        ("xor", "xor"),
+        ("nand", "nand"),
+        ("nor", "nor"),
+        ("xnor", "xnor"),
+    ] {
+        ur.insert(
+            key.to_string(),
+            Box::new(move |t, counter: Arc<AtomicUsize>| {
+                lower_universal(universal, nm, t, &counter)
+            }) as Box<_>,
+        );
+    }
 
-            let second_param = t[1].clone();
-            let second_param_2 = t[1].clone();
+    insert_shifts(&mut ur);
+    insert_relations(&mut ur, Some(universal));
+    insert_concat(&mut ur);
+    insert_prev(&mut ur);
 
-            let second_not = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![second_param, second_param_2],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-
-            let third_nand = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![first_not, second_not],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            third_nand
-        }) as Box<_>,
-    );
+    return ur;
+}
 
-    ur.insert(
-        "not".to_string(),
-        Box::new(|t, counter: Arc<AtomicUsize>| {
-            bound_check(&t, 1);
-            let yy = t[0].clone();
-            let yy_pt = t[0].clone();
-            let second = CalcNode::Function(CalcFunctionData {
-                name: "nor".to_string(),
-                brackets: false,
-                operator: None,
-                params: vec![yy, yy_pt],
-                id: counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            });
-            second
-            // unary_fn("not", t, &|x| (!(x as u64)) as f64, CalcOperatorType::Tild, width, depth)
-        }) as Box<_>,
-    );
+pub fn assemble_map_veri_nand() -> HashMap<String, EvalFunction> {
+    assemble_map_universal("nand")
+}
 
-    return ur;
+pub fn assemble_map_veri_nor() -> HashMap<String, EvalFunction> {
+    assemble_map_universal("nor")
 }
@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+
+/// Product term over `n` inputs. Input `i` is bit `n - 1 - i`, so the first input is the most
+/// significant bit of a minterm index, matching how truth tables are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cube {
+    pub bits: u32,
+    /// Inputs the term doesn't depend on.
+    pub dashes: u32,
+}
+
+impl Cube {
+    pub fn minterm(bits: u32) -> Cube {
+        Cube { bits, dashes: 0 }
+    }
+
+    pub fn contains(&self, minterm: u32) -> bool {
+        (minterm & !self.dashes) == (self.bits & !self.dashes)
+    }
+
+    pub fn literals(&self, n: usize) -> usize {
+        n - self.dashes.count_ones() as usize
+    }
+
+    /// The literals of the term as (input index, positive) pairs.
+    pub fn terms(&self, n: usize) -> Vec<(usize, bool)> {
+        (0..n)
+            .filter(|i| self.dashes & bit(n, *i) == 0)
+            .map(|i| (i, self.bits & bit(n, i) != 0))
+            .collect()
+    }
+
+    /// Whether every minterm of `other` is in the cube.
+    fn covers(&self, other: &Cube) -> bool {
+        other.dashes & !self.dashes == 0 && (self.bits ^ other.bits) & !self.dashes == 0
+    }
+
+    fn intersects(&self, other: &Cube) -> bool {
+        (self.bits ^ other.bits) & !self.dashes & !other.dashes == 0
+    }
+
+    /// The part of the cube inside `other`, as a cube over the inputs `other` leaves free.
+    fn cofactor(&self, other: &Cube, n: usize) -> Option<Cube> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let dashes = self.dashes | (mask(n) & !other.dashes);
+        Some(Cube {
+            bits: self.bits & !dashes,
+            dashes,
+        })
+    }
+}
+
+fn bit(n: usize, i: usize) -> u32 {
+    1 << (n - 1 - i)
+}
+
+fn mask(n: usize) -> u32 {
+    ((1u64 << n) - 1) as u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Minimizer {
+    Off,
+    /// Quine–McCluskey up to `EXACT_LIMIT` inputs, the heuristic above that.
+    Auto,
+    Exact,
+    Heuristic,
+}
+
+/// Most inputs Quine–McCluskey is used for by `Minimizer::Auto`.
+pub const EXACT_LIMIT: usize = 8;
+/// Most inputs a truth table is built for.
+pub const INPUT_LIMIT: usize = 16;
+
+impl TryFrom<&str> for Minimizer {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "off" => Ok(Minimizer::Off),
+            "on" | "auto" => Ok(Minimizer::Auto),
+            "exact" | "qm" => Ok(Minimizer::Exact),
+            "heuristic" | "espresso" => Ok(Minimizer::Heuristic),
+            _ => Err(format!("Unknown minimizer: {}", value)),
+        }
+    }
+}
+
+/// Minimum sum-of-products cover of `minterms`, via prime implicants and an exact cover search.
+pub fn quine_mccluskey(n: usize, minterms: &[u32]) -> Vec<Cube> {
+    let primes = prime_implicants(minterms);
+    let mut best = None;
+    exact_cover(n, minterms, &primes, &mut Vec::new(), &mut best);
+    let mut ans = best.unwrap_or_default();
+    ans.sort();
+    ans
+}
+
+fn prime_implicants(minterms: &[u32]) -> Vec<Cube> {
+    let mut current: HashSet<Cube> = minterms.iter().map(|f| Cube::minterm(*f)).collect();
+    let mut primes = Vec::new();
+    while !current.is_empty() {
+        let cubes: Vec<Cube> = current.iter().copied().collect();
+        let mut combined = HashSet::new();
+        let mut used = HashSet::new();
+        for (i, a) in cubes.iter().enumerate() {
+            for b in &cubes[i + 1..] {
+                let diff = a.bits ^ b.bits;
+                if a.dashes == b.dashes && diff.count_ones() == 1 && diff & a.dashes == 0 {
+                    combined.insert(Cube {
+                        bits: a.bits & !diff,
+                        dashes: a.dashes | diff,
+                    });
+                    used.insert(*a);
+                    used.insert(*b);
+                }
+            }
+        }
+        primes.extend(cubes.into_iter().filter(|f| !used.contains(f)));
+        current = combined;
+    }
+    primes.sort();
+    primes
+}
+
+fn cost(n: usize, cover: &[Cube]) -> (usize, usize) {
+    (cover.len(), cover.iter().map(|f| f.literals(n)).sum())
+}
+
+/// Branches on the uncovered minterm with the fewest candidate primes, which picks every
+/// essential prime first without a separate pass.
+fn exact_cover(
+    n: usize,
+    minterms: &[u32],
+    primes: &[Cube],
+    chosen: &mut Vec<Cube>,
+    best: &mut Option<Vec<Cube>>,
+) {
+    if let Some(b) = best {
+        if cost(n, chosen) >= cost(n, b) {
+            return;
+        }
+    }
+    let uncovered: Vec<u32> = minterms
+        .iter()
+        .copied()
+        .filter(|m| !chosen.iter().any(|c| c.contains(*m)))
+        .collect();
+    if uncovered.is_empty() {
+        *best = Some(chosen.clone());
+        return;
+    }
+    let candidates =
+        |m: u32| -> Vec<Cube> { primes.iter().copied().filter(|p| p.contains(m)).collect() };
+    let pivot = uncovered
+        .iter()
+        .map(|m| candidates(*m))
+        .min_by_key(|f| f.len())
+        .expect("uncovered is not empty");
+    for prime in pivot {
+        chosen.push(prime);
+        exact_cover(n, minterms, primes, chosen, best);
+        chosen.pop();
+    }
+}
+
+/// The input most cubes of `cover` depend on, as its bit, if any of them depends on one.
+fn split_bit(n: usize, cover: &[Cube]) -> Option<u32> {
+    (0..n)
+        .map(|i| bit(n, i))
+        .map(|b| (b, cover.iter().filter(|f| f.dashes & b == 0).count()))
+        .filter(|f| f.1 > 0)
+        .max_by_key(|f| f.1)
+        .map(|f| f.0)
+}
+
+/// `cover` with input `b` fixed to `value`.
+fn split(n: usize, cover: &[Cube], b: u32, value: bool) -> Vec<Cube> {
+    let half = Cube {
+        bits: if value { b } else { 0 },
+        dashes: mask(n) & !b,
+    };
+    cover.iter().filter_map(|f| f.cofactor(&half, n)).collect()
+}
+
+/// Whether `cover` holds for every input, by splitting on inputs until a half is trivially
+/// covered or trivially not.
+fn tautology(n: usize, cover: &[Cube]) -> bool {
+    if cover.iter().any(|f| f.dashes == mask(n)) {
+        return true;
+    }
+    //too few minterms between them to cover everything
+    let size: u64 = cover.iter().map(|f| 1u64 << f.dashes.count_ones()).sum();
+    if size < 1u64 << n {
+        return false;
+    }
+    match split_bit(n, cover) {
+        Some(b) => {
+            tautology(n, &split(n, cover, b, false)) && tautology(n, &split(n, cover, b, true))
+        }
+        None => false,
+    }
+}
+
+/// Cover of every minterm outside `cover`, by splitting on inputs. Cubes the two halves share
+/// don't depend on the input split on.
+fn complement(n: usize, cover: &[Cube]) -> Vec<Cube> {
+    if cover.is_empty() {
+        return vec![Cube {
+            bits: 0,
+            dashes: mask(n),
+        }];
+    }
+    if cover.iter().any(|f| f.dashes == mask(n)) {
+        return Vec::new();
+    }
+    let b = split_bit(n, cover).expect("A cube other than the universe has a literal");
+    let low = complement(n, &split(n, cover, b, false));
+    let high = complement(n, &split(n, cover, b, true));
+    let in_low: HashSet<Cube> = low.iter().copied().collect();
+    let shared: HashSet<Cube> = high
+        .iter()
+        .copied()
+        .filter(|f| in_low.contains(f))
+        .collect();
+    let literal = |cube: &Cube, value: u32| Cube {
+        bits: cube.bits | value,
+        dashes: cube.dashes & !b,
+    };
+    let mut ans: Vec<Cube> = high
+        .iter()
+        .copied()
+        .filter(|f| shared.contains(f))
+        .collect();
+    ans.extend(
+        low.iter()
+            .filter(|f| !shared.contains(f))
+            .map(|f| literal(f, 0)),
+    );
+    ans.extend(
+        high.iter()
+            .filter(|f| !shared.contains(f))
+            .map(|f| literal(f, b)),
+    );
+    ans
+}
+
+/// Espresso style heuristic: expands each cube as far as the off-set allows, then drops cubes
+/// covered by the rest, until the cover stops shrinking. Works on cubes throughout, so the cost
+/// follows the size of the covers rather than the number of minterms.
+pub fn espresso(n: usize, minterms: &[u32]) -> Vec<Cube> {
+    let mut cover: Vec<Cube> = minterms.iter().map(|f| Cube::minterm(*f)).collect();
+    let off = complement(n, &cover);
+    loop {
+        let before = cost(n, &cover);
+        cover = expand(n, &cover, &off);
+        cover = irredundant(n, &cover);
+        if cost(n, &cover) >= before {
+            break;
+        }
+    }
+    cover.sort();
+    cover
+}
+
+fn expand(n: usize, cover: &[Cube], off: &[Cube]) -> Vec<Cube> {
+    let mut ans: Vec<Cube> = Vec::new();
+    for cube in cover {
+        if ans.iter().any(|f| f.covers(cube)) {
+            continue;
+        }
+        let mut cube = *cube;
+        //inputs each off cube disagrees with the cube in; raising an input is allowed unless an
+        //off cube disagrees in that input alone
+        let mut conflicts: Vec<u32> = off
+            .iter()
+            .map(|f| (cube.bits ^ f.bits) & !cube.dashes & !f.dashes)
+            .collect();
+        let blocking = |conflicts: &[u32]| -> u32 {
+            conflicts
+                .iter()
+                .filter(|f| f.count_ones() == 1)
+                .fold(0, |acc, f| acc | f)
+        };
+        let mut blocked = blocking(&conflicts);
+        for i in 0..n {
+            let b = bit(n, i);
+            if (cube.dashes | blocked) & b != 0 {
+                continue;
+            }
+            cube = Cube {
+                bits: cube.bits & !b,
+                dashes: cube.dashes | b,
+            };
+            conflicts.iter_mut().for_each(|f| *f &= !b);
+            blocked = blocking(&conflicts);
+        }
+        ans.push(cube);
+    }
+    ans
+}
+
+fn irredundant(n: usize, cover: &[Cube]) -> Vec<Cube> {
+    let mut ans = cover.to_vec();
+    //try to drop the smallest cubes first, they are the most likely to be redundant
+    ans.sort_by_key(|f| std::cmp::Reverse(f.literals(n)));
+    let mut i = 0;
+    while i < ans.len() {
+        let rest: Vec<Cube> = ans
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .filter_map(|(_, c)| c.cofactor(&ans[i], n))
+            .collect();
+        if tautology(n, &rest) {
+            ans.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    ans
+}
@@ -1,7 +1,23 @@
+pub mod bdd;
+pub mod bus;
+pub mod cells;
+pub mod command;
 pub mod context;
 pub mod entity;
+pub mod equiv;
+pub mod export;
+pub mod format;
+pub mod fsm;
 pub mod funcs;
+pub mod minimize;
+pub mod netlist;
 pub mod node;
+pub mod seq;
+pub mod sim;
+pub mod stats;
+pub mod testbench;
+pub mod trace;
+pub mod truth;
 
 use std::sync::{atomic::AtomicUsize, Arc};
 
@@ -0,0 +1,830 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Write;
+
+use super::{
+    bus::{group_bits, split_bit, BusRange},
+    cells::Cell,
+    node::{CalcNode, CalcOperatorType},
+};
+
+pub fn is_commutative(kind: &str) -> bool {
+    matches!(kind, "and" | "or" | "xor" | "nand" | "nor" | "xnor")
+}
+
+/// Output of a gate of kind `kind`. Only the low bit of the result is meaningful, apart from
+/// shift amounts which are used whole.
+pub fn gate_value(kind: &str, ins: &[u64]) -> Result<u64, String> {
+    let all = |f: fn(u64, u64) -> u64| ins.iter().copied().reduce(f).unwrap_or(0);
+    let ans = match kind {
+        "and" => all(|x, y| x & y),
+        "or" => all(|x, y| x | y),
+        "xor" => all(|x, y| x ^ y),
+        "nand" => !all(|x, y| x & y),
+        "nor" => !all(|x, y| x | y),
+        "xnor" => !all(|x, y| x ^ y),
+        "not" => !ins[0],
+        "buf" => ins[0],
+        "shl" => (ins[0] & 1).checked_shl(ins[1] as u32).unwrap_or(0),
+        "shr" | "ashr" if ins[1] > 0 => 0,
+        "shr" | "ashr" | "rotl" | "rotr" => ins[0],
+        _ => return Err(format!("Can't evaluate gate {}", kind)),
+    };
+    Ok(ans)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Signal {
+    Input(String),
+    Wire(usize),
+    Constant(i64),
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Signal::Input(x) => x.fmt(f),
+            Signal::Wire(x) => write!(f, "w_{}", x),
+            Signal::Constant(x) => x.fmt(f),
+        }
+    }
+}
+
+/// One gate of the DAG, driving the wire `w_{id}` unless the wire is named.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gate {
+    pub id: usize,
+    pub kind: String,
+    pub operator: Option<CalcOperatorType>,
+    pub inputs: Vec<Signal>,
+}
+
+/// Register bit loading `d` on every rising edge of `clock`. Gates read it as the input
+/// signal `name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlipFlop {
+    pub name: String,
+    pub d: Signal,
+    pub clock: String,
+    /// Synchronous, active high reset signal and the value it loads.
+    pub reset: Option<(String, bool)>,
+}
+
+impl FlipFlop {
+    /// Value the register holds before the first edge.
+    pub fn initial(&self) -> bool {
+        self.reset.as_ref().is_some_and(|f| f.1)
+    }
+}
+
+/// Flattened gate DAG of one module. Gates are kept in topological order, every gate comes
+/// after the gates driving its inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Netlist {
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<(String, Signal)>,
+    pub gates: Vec<Gate>,
+    /// Registers, in the order they were added. Their next values are computed by the gates
+    /// like the outputs are.
+    pub flops: Vec<FlipFlop>,
+    pub style: VerilogStyle,
+    /// Library cells the gates instantiate, for a netlist mapped onto a cell library.
+    pub cells: Vec<Cell>,
+    /// Reports from the passes run over the netlist, emitted as comments.
+    pub notes: Vec<String>,
+    /// User function computing each wire, for the wires that have one.
+    pub labels: HashMap<usize, String>,
+    /// Names of the wires not emitted as `w_{id}`, set by `number_wires`.
+    pub wire_names: HashMap<usize, String>,
+    /// Tree each output port was evaluated from, before any pass rewrote it.
+    pub references: Vec<(String, CalcNode)>,
+    /// Tree each register bit loads, like `references` for the outputs.
+    pub register_references: Vec<(String, CalcNode)>,
+    /// Register holding each `prev` node, by node id, so a shared one is a single register.
+    pub prevs: HashMap<usize, String>,
+}
+
+impl Netlist {
+    pub fn new(name: &str) -> Netlist {
+        Netlist {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds the gates of `node` and drives the output port `name` with its result. Functions
+    /// sharing an id are the same gate and only added once.
+    pub fn add_output(&mut self, name: &str, node: &CalcNode) {
+        let mut seen: HashSet<usize> = self.gates.iter().map(|f| f.id).collect();
+        let signal = self.add_node(node, &mut seen);
+        self.outputs.push((name.to_string(), signal));
+    }
+
+    fn add_node(&mut self, node: &CalcNode, seen: &mut HashSet<usize>) -> Signal {
+        match node {
+            CalcNode::Text(x) => {
+                if !self.inputs.contains(x) {
+                    self.inputs.push(x.to_string());
+                }
+                Signal::Input(x.to_string())
+            }
+            CalcNode::SingleValue(x) => Signal::Constant(*x as i64),
+            //the value of the operand one cycle ago, held by a register of its own
+            CalcNode::Function(x) if x.name == "prev" => {
+                if let Some(name) = self.prevs.get(&x.id) {
+                    return Signal::Input(name.to_string());
+                }
+                let d = self.add_node(&x.params[0], seen);
+                let name = format!("prev_{}", self.flops.len());
+                self.flops.push(FlipFlop {
+                    name: name.to_string(),
+                    d,
+                    clock: String::new(),
+                    reset: None,
+                });
+                self.prevs.insert(x.id, name.to_string());
+                Signal::Input(name)
+            }
+            CalcNode::Function(x) => {
+                let inputs = x.params.iter().map(|f| self.add_node(f, seen)).collect();
+                if seen.insert(x.id) {
+                    self.gates.push(Gate {
+                        id: x.id,
+                        kind: x.name.to_string(),
+                        operator: x.operator,
+                        inputs,
+                    });
+                }
+                Signal::Wire(x.id)
+            }
+            _ => panic!("Type not allowed"),
+        }
+    }
+
+    /// Adds the gates of `node` and loads the register bit `name` with its result.
+    pub fn add_register(
+        &mut self,
+        name: &str,
+        node: &CalcNode,
+        clock: &str,
+        reset: Option<(String, bool)>,
+    ) {
+        let mut seen: HashSet<usize> = self.gates.iter().map(|f| f.id).collect();
+        let d = self.add_node(node, &mut seen);
+        self.flops.push(FlipFlop {
+            name: name.to_string(),
+            d,
+            clock: clock.to_string(),
+            reset,
+        });
+    }
+
+    /// Once every register is added: `prev` registers take `clock`, the registers stop being
+    /// inputs and their clocks and resets become inputs.
+    pub fn connect_registers(&mut self, clock: &str) {
+        for flop in self.flops.iter_mut().filter(|f| f.clock.is_empty()) {
+            flop.clock = clock.to_string();
+        }
+        let flops = &self.flops;
+        self.inputs.retain(|f| !flops.iter().any(|r| r.name == *f));
+        for flop in flops {
+            let reset = flop.reset.as_ref().map(|f| &f.0);
+            for x in std::iter::once(&flop.clock).chain(reset) {
+                if !self.inputs.contains(x) {
+                    self.inputs.push(x.to_string());
+                }
+            }
+        }
+    }
+
+    /// Hash-consing pass: merges gates that compute the same function of the same signals, so
+    /// structurally equal subexpressions share one wire no matter how they were built. Inputs
+    /// of commutative gates are compared as a set. Returns the gate count before and after.
+    pub fn hash_cons(&mut self) -> (usize, usize) {
+        let before = self.gates.len();
+        let mut canonical: HashMap<(String, Vec<Signal>), usize> = HashMap::new();
+        let mut replaced: HashMap<usize, usize> = HashMap::new();
+        let resolve = |s: &Signal, replaced: &HashMap<usize, usize>| match s {
+            Signal::Wire(x) => Signal::Wire(*replaced.get(x).unwrap_or(x)),
+            _ => s.clone(),
+        };
+
+        let mut gates = Vec::new();
+        for mut gate in std::mem::take(&mut self.gates) {
+            gate.inputs = gate.inputs.iter().map(|f| resolve(f, &replaced)).collect();
+            let mut key_inputs = gate.inputs.clone();
+            if is_commutative(&gate.kind) {
+                key_inputs.sort();
+            }
+            let key = (gate.kind.to_string(), key_inputs);
+            if let Some(id) = canonical.get(&key) {
+                if let Some(label) = self.labels.remove(&gate.id) {
+                    self.labels.entry(*id).or_insert(label);
+                }
+                replaced.insert(gate.id, *id);
+            } else {
+                canonical.insert(key, gate.id);
+                gates.push(gate);
+            }
+        }
+        self.gates = gates;
+        for output in self.outputs.iter_mut() {
+            output.1 = resolve(&output.1, &replaced);
+        }
+        for flop in self.flops.iter_mut() {
+            flop.d = resolve(&flop.d, &replaced);
+        }
+        (before, self.gates.len())
+    }
+
+    /// Gates on the longest path from an input or register to an output or register.
+    pub fn depth(&self) -> usize {
+        let mut depths = HashMap::new();
+        for gate in &self.gates {
+            let d = gate.inputs.iter().map(|f| Self::depth_in(f, &depths)).max();
+            depths.insert(gate.id, d.unwrap_or(0) + 1);
+        }
+        self.sinks()
+            .map(|f| Self::depth_in(f, &depths))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Signals leaving the gates: the outputs, then the next values of the registers.
+    pub fn sinks(&self) -> impl Iterator<Item = &Signal> {
+        let outputs = self.outputs.iter().map(|f| &f.1);
+        outputs.chain(self.flops.iter().map(|f| &f.d))
+    }
+
+    /// Register named `name`, if any.
+    pub fn flop(&self, name: &str) -> Option<&FlipFlop> {
+        self.flops.iter().find(|f| f.name == name)
+    }
+
+    /// Tree-height reduction: a chain of one associative gate type, where every inner gate is
+    /// read only by the next, is rebuilt as a tree pairing the shallowest operands first. The
+    /// last gate of the chain keeps its wire. Returns the depth before and after.
+    pub fn balance(&mut self) -> (usize, usize) {
+        let before = self.depth();
+        let mut readers: HashMap<usize, Vec<usize>> = HashMap::new();
+        for gate in &self.gates {
+            for input in &gate.inputs {
+                if let Signal::Wire(x) = input {
+                    readers.entry(*x).or_default().push(gate.id);
+                }
+            }
+        }
+        for signal in self.sinks() {
+            if let Signal::Wire(x) = signal {
+                readers.entry(*x).or_default().push(usize::MAX);
+            }
+        }
+        let kinds: HashMap<usize, String> = self
+            .gates
+            .iter()
+            .map(|f| (f.id, f.kind.to_string()))
+            .collect();
+        let associative = |kind: &str| matches!(kind, "and" | "or" | "xor");
+        //gates folded into the chain of their only reader
+        let absorbed = |id: usize| match readers.get(&id).map(|f| f.as_slice()) {
+            Some([reader]) => associative(&kinds[&id]) && kinds.get(reader) == Some(&kinds[&id]),
+            _ => false,
+        };
+
+        let mut next_id = self.gates.iter().map(|f| f.id + 1).max().unwrap_or(0);
+        let by_id: HashMap<usize, Gate> = self.gates.iter().map(|f| (f.id, f.clone())).collect();
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        let mut gates = Vec::new();
+        for gate in std::mem::take(&mut self.gates) {
+            if absorbed(gate.id) {
+                continue;
+            }
+            if !associative(&gate.kind) {
+                let d = gate.inputs.iter().map(|f| Self::depth_in(f, &depths)).max();
+                depths.insert(gate.id, d.unwrap_or(0) + 1);
+                gates.push(gate);
+                continue;
+            }
+
+            let mut operands = Vec::new();
+            let mut pending: Vec<Signal> = gate.inputs.iter().rev().cloned().collect();
+            while let Some(input) = pending.pop() {
+                match input {
+                    Signal::Wire(x) if absorbed(x) => {
+                        pending.extend(by_id[&x].inputs.iter().rev().cloned())
+                    }
+                    x => operands.push(x),
+                }
+            }
+            let mut operands: Vec<(usize, usize, Signal)> = operands
+                .into_iter()
+                .enumerate()
+                .map(|(i, f)| (Self::depth_in(&f, &depths), i, f))
+                .collect();
+            let mut order = operands.len();
+            while operands.len() > 2 {
+                operands.sort();
+                let (da, _, a) = operands.remove(0);
+                let (db, _, b) = operands.remove(0);
+                let id = next_id;
+                next_id += 1;
+                gates.push(Gate {
+                    id,
+                    kind: gate.kind.to_string(),
+                    operator: gate.operator,
+                    inputs: vec![a, b],
+                });
+                depths.insert(id, da.max(db) + 1);
+                operands.push((da.max(db) + 1, order, Signal::Wire(id)));
+                order += 1;
+            }
+            operands.sort_by_key(|f| f.1);
+            let d = operands.iter().map(|f| f.0).max().unwrap_or(0);
+            depths.insert(gate.id, d + 1);
+            gates.push(Gate {
+                inputs: operands.into_iter().map(|f| f.2).collect(),
+                ..gate
+            });
+        }
+        self.gates = gates;
+        (before, self.depth())
+    }
+
+    /// Renumbers the wires in gate order: unlabelled wires become `w_0`, `w_1`, ... and
+    /// labelled ones `w_{label}_0`, `w_{label}_1`, ... counted per label.
+    pub fn number_wires(&mut self) {
+        let unlabelled = self
+            .gates
+            .iter()
+            .filter(|f| !self.labels.contains_key(&f.id));
+        let (mut plain, mut named) = (0, unlabelled.count());
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut ids: HashMap<usize, usize> = HashMap::new();
+        let (mut labels, mut names) = (HashMap::new(), HashMap::new());
+        let renumber = |s: &Signal, ids: &HashMap<usize, usize>| match s {
+            Signal::Wire(x) => Signal::Wire(ids[x]),
+            _ => s.clone(),
+        };
+        for gate in self.gates.iter_mut() {
+            let id = match self.labels.get(&gate.id) {
+                Some(label) => {
+                    let k = counts.entry(label).or_insert(0);
+                    names.insert(named, format!("w_{}_{}", label, k));
+                    labels.insert(named, label.to_string());
+                    *k += 1;
+                    named += 1;
+                    named - 1
+                }
+                None => {
+                    plain += 1;
+                    plain - 1
+                }
+            };
+            ids.insert(gate.id, id);
+            gate.id = id;
+            gate.inputs = gate.inputs.iter().map(|f| renumber(f, &ids)).collect();
+        }
+        for output in self.outputs.iter_mut() {
+            output.1 = renumber(&output.1, &ids);
+        }
+        for flop in self.flops.iter_mut() {
+            flop.d = renumber(&flop.d, &ids);
+        }
+        self.labels = labels;
+        self.wire_names = names;
+    }
+
+    /// Name `signal` is emitted under.
+    pub fn name_of(&self, signal: &Signal) -> String {
+        match signal {
+            Signal::Wire(x) => self.wire_name(*x),
+            _ => signal.to_string(),
+        }
+    }
+
+    /// Name of the wire driven by gate `id`.
+    pub fn wire_name(&self, id: usize) -> String {
+        match self.wire_names.get(&id) {
+            Some(x) => x.to_string(),
+            None => format!("w_{}", id),
+        }
+    }
+
+    fn depth_in(signal: &Signal, depths: &HashMap<usize, usize>) -> usize {
+        match signal {
+            Signal::Wire(x) => depths[x],
+            _ => 0,
+        }
+    }
+
+    /// Value of every wire for one assignment of the inputs. Every wire is one bit wide.
+    /// Registers not assigned hold their initial value.
+    pub fn evaluate(&self, inputs: &HashMap<String, bool>) -> Result<HashMap<usize, bool>, String> {
+        let mut wires = HashMap::new();
+        for gate in &self.gates {
+            let ins = gate
+                .inputs
+                .iter()
+                .map(|f| self.value_of(f, inputs, &wires))
+                .collect::<Result<Vec<u64>, String>>()?;
+            let value = match self.cell(&gate.kind) {
+                Some(cell) => cell.evaluate(&ins.iter().map(|f| f & 1 == 1).collect::<Vec<_>>())?,
+                None => gate_value(&gate.kind, &ins)? & 1 == 1,
+            };
+            wires.insert(gate.id, value);
+        }
+        Ok(wires)
+    }
+
+    /// Values of the outputs, in port order, for one assignment of the inputs.
+    pub fn output_values(&self, inputs: &HashMap<String, bool>) -> Result<Vec<bool>, String> {
+        let wires = self.evaluate(inputs)?;
+        self.outputs
+            .iter()
+            .map(|(_, f)| self.value_of(f, inputs, &wires).map(|x| x & 1 == 1))
+            .collect()
+    }
+
+    /// Library cell a gate of kind `kind` instantiates, if any.
+    pub fn cell(&self, kind: &str) -> Option<&Cell> {
+        self.cells.iter().find(|f| f.name == kind)
+    }
+
+    fn value_of(
+        &self,
+        signal: &Signal,
+        inputs: &HashMap<String, bool>,
+        wires: &HashMap<usize, bool>,
+    ) -> Result<u64, String> {
+        match signal {
+            Signal::Input(x) => inputs
+                .get(x)
+                .copied()
+                .or_else(|| self.flop(x).map(|f| f.initial()))
+                .map(|f| f as u64)
+                .ok_or_else(|| format!("No value for input {}", x)),
+            Signal::Wire(x) => Ok(wires[x] as u64),
+            Signal::Constant(x) => Ok(*x as u64),
+        }
+    }
+
+    /// Puts the user supplied `ports` first, in the given order. A port naming a bus brings
+    /// all of its bits. Inferred inputs not listed keep their order after them.
+    pub fn order_inputs(&mut self, ports: &[String]) {
+        let mut ordered = Vec::new();
+        for port in ports {
+            let bits = self.inputs.iter().filter(|f| split_bit(f).0 == port);
+            let bits: Vec<String> = bits.filter(|f| *f != port).cloned().collect();
+            if bits.is_empty() {
+                ordered.push(port.to_string());
+            } else {
+                ordered.extend(bits);
+            }
+        }
+        for x in &self.inputs {
+            if !ordered.contains(x) {
+                ordered.push(x.to_string());
+            }
+        }
+        self.inputs = ordered;
+    }
+
+    /// Port declarations for `names`, with the bits of a bus gathered into one vector port.
+    /// The direction and range are only repeated when they change.
+    fn port_list(direction: &str, names: &[&str], ports: &mut Vec<String>) {
+        let mut last: Option<Option<BusRange>> = None;
+        for (name, range) in group_bits(names) {
+            if last == Some(range) {
+                ports.push(name.to_string());
+                continue;
+            }
+            match range {
+                Some(r) => ports.push(format!("{} {} {}", direction, r, name)),
+                None => ports.push(format!("{} {}", direction, name)),
+            }
+            last = Some(range);
+        }
+    }
+
+    pub fn to_verilog(&self) -> String {
+        let mut ports = Vec::new();
+        let inputs: Vec<&str> = self.inputs.iter().map(|f| f.as_str()).collect();
+        Self::port_list("input", &inputs, &mut ports);
+        //an output that is a register is declared as one
+        let is_reg = |name: &str| {
+            self.outputs
+                .iter()
+                .any(|f| self.is_register_output(f) && f.0 == name)
+        };
+        let outputs: Vec<&str> = self.outputs.iter().map(|f| f.0.as_str()).collect();
+        for run in outputs.chunk_by(|x, y| is_reg(x) == is_reg(y)) {
+            let direction = if is_reg(run[0]) {
+                "output reg"
+            } else {
+                "output"
+            };
+            Self::port_list(direction, run, &mut ports);
+        }
+
+        let mut sout = String::new();
+        for note in &self.notes {
+            writeln!(sout, "// {}", note).expect("msg");
+        }
+        writeln!(sout, "module {}({});", self.name, ports.join(", ")).expect("msg");
+        let (wires, lines, next) = match self.style {
+            VerilogStyle::Gates => self.gate_lines(),
+            VerilogStyle::Assign => self.assign_lines(),
+        };
+        let regs: Vec<&str> = self
+            .flops
+            .iter()
+            .filter(|f| {
+                !self
+                    .outputs
+                    .iter()
+                    .any(|x| x.0 == f.name && self.is_register_output(x))
+            })
+            .map(|f| f.name.as_str())
+            .collect();
+        for (name, range) in group_bits(&regs) {
+            match range {
+                Some(r) => writeln!(sout, "    reg {} {};", r, name).expect("msg"),
+                None => writeln!(sout, "    reg {};", name).expect("msg"),
+            }
+        }
+        if !wires.is_empty() {
+            writeln!(sout, "    wire {};", wires.join(", ")).expect("msg");
+        }
+        for line in lines {
+            writeln!(sout, "    {}", line).expect("msg");
+        }
+        for line in self.always_blocks(&next) {
+            writeln!(sout, "    {}", line).expect("msg");
+        }
+        sout.push_str("endmodule");
+        sout
+    }
+
+    /// Whether `output` is a register driving the port of its own name.
+    pub fn is_register_output(&self, output: &(String, Signal)) -> bool {
+        output.1 == Signal::Input(output.0.to_string()) && self.flop(&output.0).is_some()
+    }
+
+    /// One `always @(posedge clock)` block for each clock and reset the registers use, in
+    /// order of first use. `next` holds the next value of each register.
+    fn always_blocks(&self, next: &[String]) -> Vec<String> {
+        let mut clockings: Vec<(&str, Option<&str>)> = Vec::new();
+        for flop in &self.flops {
+            let clocking = (
+                flop.clock.as_str(),
+                flop.reset.as_ref().map(|f| f.0.as_str()),
+            );
+            if !clockings.contains(&clocking) {
+                clockings.push(clocking);
+            }
+        }
+        let mut lines = Vec::new();
+        for (clock, reset) in clockings {
+            let flops: Vec<(&FlipFlop, &String)> = self
+                .flops
+                .iter()
+                .zip(next)
+                .filter(|(f, _)| {
+                    f.clock == clock && f.reset.as_ref().map(|x| x.0.as_str()) == reset
+                })
+                .collect();
+            lines.push(format!("always @(posedge {}) begin", clock));
+            let indent = match reset {
+                Some(reset) => {
+                    lines.push(format!("    if ({}) begin", reset));
+                    for (flop, _) in &flops {
+                        let value = flop.initial() as u8;
+                        lines.push(format!("        {} <= 1'b{};", flop.name, value));
+                    }
+                    lines.push("    end else begin".to_string());
+                    "        "
+                }
+                None => "    ",
+            };
+            for (flop, d) in &flops {
+                lines.push(format!("{}{} <= {};", indent, flop.name, d));
+            }
+            if reset.is_some() {
+                lines.push("    end".to_string());
+            }
+            lines.push("end".to_string());
+        }
+        lines
+    }
+
+    fn gate_lines(&self) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let wires = self.gates.iter().map(|f| self.wire_name(f.id)).collect();
+        let mut lines: Vec<String> = self
+            .gates
+            .iter()
+            .map(|f| match self.cell(&f.kind) {
+                Some(cell) => cell.instance(f, self),
+                None => self.gate_to_verilog(f),
+            })
+            .collect();
+        for output @ (name, signal) in &self.outputs {
+            if !self.is_register_output(output) {
+                lines.push(format!("assign {} = {};", name, self.name_of(signal)));
+            }
+        }
+        let next = self.flops.iter().map(|f| self.name_of(&f.d)).collect();
+        (wires, lines, next)
+    }
+
+    /// Continuous assignments. A gate read exactly once is folded into the expression reading
+    /// it, every other gate keeps its wire.
+    fn assign_lines(&self) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mut fanout: HashMap<usize, usize> = HashMap::new();
+        let reads = self.gates.iter().flat_map(|f| &f.inputs);
+        for signal in reads.chain(self.sinks()) {
+            if let Signal::Wire(x) = signal {
+                *fanout.entry(*x).or_default() += 1;
+            }
+        }
+
+        let mut folded: HashMap<usize, Expr> = HashMap::new();
+        let operand = |signal: &Signal, folded: &mut HashMap<usize, Expr>| match signal {
+            Signal::Wire(x) => folded
+                .remove(x)
+                .unwrap_or_else(|| Expr::Signal(self.name_of(signal))),
+            _ => Expr::Signal(signal.to_string()),
+        };
+        let (mut wires, mut lines) = (Vec::new(), Vec::new());
+        for gate in &self.gates {
+            let ins = gate
+                .inputs
+                .iter()
+                .map(|f| operand(f, &mut folded))
+                .collect();
+            let expr = Expr::of_gate(&gate.kind, ins);
+            if fanout.get(&gate.id) == Some(&1) {
+                folded.insert(gate.id, expr);
+            } else {
+                let wire = self.wire_name(gate.id);
+                lines.push(format!("assign {} = {};", wire, expr));
+                wires.push(wire);
+            }
+        }
+        for output @ (name, signal) in &self.outputs {
+            if !self.is_register_output(output) {
+                lines.push(format!(
+                    "assign {} = {};",
+                    name,
+                    operand(signal, &mut folded)
+                ));
+            }
+        }
+        let next = self
+            .flops
+            .iter()
+            .map(|f| operand(&f.d, &mut folded).to_string())
+            .collect();
+        (wires, lines, next)
+    }
+
+    fn gate_to_verilog(&self, gate: &Gate) -> String {
+        let wire = self.wire_name(gate.id);
+        let ins: Vec<String> = gate.inputs.iter().map(|f| self.name_of(f)).collect();
+        if let Some(op) = gate.operator.filter(|op| op.is_shift()) {
+            return format!("assign {} = {}{}{};", wire, ins[0], op, ins[1]);
+        }
+        //rotating a single bit without a width leaves it where it is
+        if (gate.kind == "rotl" || gate.kind == "rotr") && ins.len() == 2 {
+            return format!("assign {} = {};", wire, ins[0]);
+        }
+        if (gate.kind == "rotl" || gate.kind == "rotr") && ins.len() == 3 {
+            let (first, second) = if gate.kind == "rotl" {
+                ("<<", ">>")
+            } else {
+                (">>", "<<")
+            };
+            return format!(
+                "assign {} = ({} {} {}) | ({} {} ({} - {}));",
+                wire, ins[0], first, ins[1], ins[0], second, ins[2], ins[1]
+            );
+        }
+        format!("{}({}, {});", gate.kind, wire, ins.join(", "))
+    }
+}
+
+/// How `Netlist::to_verilog` writes the gates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerilogStyle {
+    /// One primitive instance per gate, e.g. `and(w_0, a, b);`.
+    #[default]
+    Gates,
+    /// Continuous assignments, e.g. `assign y = a & b | c;`.
+    Assign,
+}
+
+/// Right hand side of a continuous assignment.
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Signal(String),
+    Prefix(CalcOperatorType, Box<Expr>),
+    Infix(CalcOperatorType, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn infix(op: CalcOperatorType, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Infix(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    fn not(e: Expr) -> Expr {
+        Expr::Prefix(CalcOperatorType::Tild, Box::new(e))
+    }
+
+    /// Expression computing gate `kind` of `ins`, following `gate_value`.
+    fn of_gate(kind: &str, ins: Vec<Expr>) -> Expr {
+        use CalcOperatorType::*;
+        let fold = |op, ins: Vec<Expr>| {
+            ins.into_iter()
+                .reduce(|x, y| Expr::infix(op, x, y))
+                .expect("Gates have inputs")
+        };
+        match kind {
+            "and" => fold(Ampersand, ins),
+            "or" => fold(Pipe, ins),
+            "xor" => fold(Caret, ins),
+            "nand" => Expr::not(fold(Ampersand, ins)),
+            "nor" => Expr::not(fold(Pipe, ins)),
+            "xnor" => Expr::not(fold(Caret, ins)),
+            "not" => Expr::not(ins.into_iter().next().expect("Gates have inputs")),
+            "shl" => fold(ShiftLeft, ins),
+            "shr" => fold(ShiftRight, ins),
+            "ashr" => fold(ArithmeticShiftRight, ins),
+            "rotl" | "rotr" if ins.len() == 3 => {
+                let (first, second) = if kind == "rotl" {
+                    (ShiftLeft, ShiftRight)
+                } else {
+                    (ShiftRight, ShiftLeft)
+                };
+                let (x, n, w) = (ins[0].clone(), ins[1].clone(), ins[2].clone());
+                Expr::infix(
+                    Pipe,
+                    Expr::infix(first, x.clone(), n.clone()),
+                    Expr::infix(second, x, Expr::infix(Minus, w, n)),
+                )
+            }
+            //a one bit rotate, or a buffer
+            _ if matches!(kind, "buf" | "rotl" | "rotr") => {
+                ins.into_iter().next().expect("Gates have inputs")
+            }
+            _ => {
+                let ins: Vec<String> = ins.iter().map(|f| f.to_string()).collect();
+                Expr::Signal(format!("{}({})", kind, ins.join(", ")))
+            }
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Signal(_) => 0,
+            Expr::Prefix(op, _) | Expr::Infix(op, _, _) => {
+                op.verilog_precedence().unwrap_or(u8::MAX)
+            }
+        }
+    }
+}
+
+/// Writes the expression with only the parentheses Verilog's precedence needs. Operators
+/// group left to right, so a right operand of the same precedence is only left bare when it is
+/// the same associative operator.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wrap = |e: &Expr, parens: bool| {
+            if parens {
+                format!("({})", e)
+            } else {
+                e.to_string()
+            }
+        };
+        let p = self.precedence();
+        match self {
+            Expr::Signal(x) => x.fmt(f),
+            Expr::Prefix(op, e) => write!(f, "{}{}", op.symbol(), wrap(e, e.precedence() > p)),
+            Expr::Infix(op, lhs, rhs) => {
+                let associative = matches!(
+                    op,
+                    CalcOperatorType::Ampersand | CalcOperatorType::Pipe | CalcOperatorType::Caret
+                );
+                let same = matches!(**rhs, Expr::Infix(x, _, _) if x == *op);
+                let rhs_parens =
+                    rhs.precedence() > p || (rhs.precedence() == p && !(associative && same));
+                write!(
+                    f,
+                    "{} {} {}",
+                    wrap(lhs, lhs.precedence() > p),
+                    op.symbol(),
+                    wrap(rhs, rhs_parens)
+                )
+            }
+        }
+    }
+}
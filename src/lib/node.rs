@@ -41,7 +41,10 @@ impl fmt::Display for CalcFunctionData {
             }
             self.visit(&mut |fre, is_last| {
                 let opr = self.operator.ok_or_else(|| anyhow!("No Operator"))?;
-                if !opr.is_postfix()? {
+                if opr.is_suffix() {
+                    fre.fmt(f)?;
+                    opr.fmt(f)?;
+                } else if !opr.is_postfix()? {
                     opr.fmt(f)?;
                     fre.fmt(f)?;
                 } else if !is_last {
@@ -57,6 +60,8 @@ impl fmt::Display for CalcFunctionData {
                 ")".fmt(f)?;
             };
             Ok(())
+        } else if self.params.is_empty() {
+            f.write_str(&self.name)
         } else {
             write!(f, "{}(", self.name).expect("Couldn't display");
             self.visit(&mut |fre, is_last| {
@@ -107,90 +112,252 @@ pub enum CalcOperatorType {
     ParenthesisOpen,
     ParenthesisClose,
     Caret,
+    /// `**`, the calculator's other spelling of `^`. Verilog reads it as exponentiation, so
+    /// unlike `^` it has no gates there.
+    DoubleAsterisk,
     Modulus,
     Comma,
     Ampersand,
     Pipe,
     Tild,
     Equals,
+    Exclamation,
+    Percent,
+    ShiftLeft,
+    ShiftRight,
+    ArithmeticShiftRight,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    EqualsEquals,
+    NotEquals,
+    LogicalAnd,
+    LogicalOr,
+    Arrow,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CalcOperatorFixity {
+    /// Written before its operand, e.g. `~a`.
+    Prefix,
+    /// Written after its operand, e.g. `5!`.
+    Suffix,
+    /// Binary, grouping left to right.
+    Infix,
+    /// Binary, grouping right to left.
+    InfixRight,
+    /// Brackets, commas and assignment, handled by the parser itself.
+    Punctuation,
 }
 
+pub struct CalcOperatorSpec {
+    pub symbol: &'static str,
+    pub operator: CalcOperatorType,
+    pub binding: Option<&'static str>,
+    pub fixity: CalcOperatorFixity,
+    /// Lower binds tighter; `None` for operators without a precedence pass. Operators sharing
+    /// a level share a fixity and group in one pass.
+    pub precedence: Option<u8>,
+    /// Precedence in Verilog, lower binds tighter. Not the calculator's precedence: there `^`
+    /// binds tighter than `&`, in Verilog it sits between `&` and `|`.
+    pub verilog_precedence: Option<u8>,
+}
+
+const fn spec(
+    symbol: &'static str,
+    operator: CalcOperatorType,
+    binding: Option<&'static str>,
+    fixity: CalcOperatorFixity,
+    precedence: Option<u8>,
+    verilog_precedence: Option<u8>,
+) -> CalcOperatorSpec {
+    CalcOperatorSpec {
+        symbol,
+        operator,
+        binding,
+        fixity,
+        precedence,
+        verilog_precedence,
+    }
+}
+
+/// Every operator the lexer knows. The first entry for an operator is its canonical spelling,
+/// later entries are aliases.
+pub const OPERATORS: &[CalcOperatorSpec] = {
+    use CalcOperatorFixity::*;
+    use CalcOperatorType::*;
+    &[
+        spec("(", ParenthesisOpen, None, Punctuation, None, None),
+        spec(")", ParenthesisClose, None, Punctuation, None, None),
+        spec(",", Comma, None, Punctuation, None, None),
+        spec("=", Equals, None, Punctuation, None, None),
+        spec(":=", Equals, None, Punctuation, None, None),
+        spec(
+            "!",
+            Exclamation,
+            Some("factorial"),
+            Suffix,
+            Some(1),
+            Some(1),
+        ),
+        spec("%", Modulus, Some("modulus"), Infix, Some(5), Some(2)),
+        //the parser turns a `%` without a right operand into this
+        spec("%", Percent, Some("percent"), Suffix, Some(2), None),
+        spec("~", Tild, Some("not"), Prefix, Some(3), Some(1)),
+        spec("^", Caret, Some("power"), InfixRight, Some(4), Some(8)),
+        spec(
+            "**",
+            DoubleAsterisk,
+            Some("exponent"),
+            InfixRight,
+            Some(4),
+            None,
+        ),
+        spec("*", Asterisk, Some("multiply"), Infix, Some(5), Some(2)),
+        spec("/", Slash, Some("inverse"), Infix, Some(5), Some(2)),
+        spec("+", Plus, Some("add"), Infix, Some(6), Some(3)),
+        spec("-", Minus, Some("negate"), Infix, Some(6), Some(3)),
+        spec("<<", ShiftLeft, Some("shl"), Infix, Some(7), Some(4)),
+        spec(">>", ShiftRight, Some("shr"), Infix, Some(7), Some(4)),
+        spec(
+            ">>>",
+            ArithmeticShiftRight,
+            Some("ashr"),
+            Infix,
+            Some(7),
+            Some(4),
+        ),
+        spec("<", Less, Some("less"), Infix, Some(8), Some(5)),
+        spec("<=", LessEquals, Some("lessequal"), Infix, Some(8), Some(5)),
+        spec(">", Greater, Some("greater"), Infix, Some(8), Some(5)),
+        spec(
+            ">=",
+            GreaterEquals,
+            Some("greaterequal"),
+            Infix,
+            Some(8),
+            Some(5),
+        ),
+        spec("==", EqualsEquals, Some("equal"), Infix, Some(9), Some(6)),
+        spec("!=", NotEquals, Some("notequal"), Infix, Some(9), Some(6)),
+        spec("&", Ampersand, Some("and"), Infix, Some(10), Some(7)),
+        spec("|", Pipe, Some("or"), Infix, Some(11), Some(9)),
+        spec("&&", LogicalAnd, Some("land"), Infix, Some(12), Some(10)),
+        spec("||", LogicalOr, Some("lor"), Infix, Some(13), Some(11)),
+        spec("->", Arrow, Some("implies"), InfixRight, Some(14), None),
+    ]
+};
+
 impl CalcOperatorType {
+    fn spec(&self) -> &'static CalcOperatorSpec {
+        OPERATORS
+            .iter()
+            .find(|f| f.operator == *self)
+            .expect("Every operator is registered in OPERATORS")
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        self.spec().symbol
+    }
+
+    pub fn fixity(&self) -> CalcOperatorFixity {
+        self.spec().fixity
+    }
+
     pub fn get_function_bindings(&self) -> Result<&str, CalcNodeError> {
-        match self {
-            CalcOperatorType::Plus => Ok("add"),
-            CalcOperatorType::Minus => Ok("negate"),
-            CalcOperatorType::Asterisk => Ok("multiply"),
-            CalcOperatorType::Slash => Ok("inverse"),
-            CalcOperatorType::Caret => Ok("power"),
-            CalcOperatorType::Modulus => Ok("modulus"),
-            CalcOperatorType::Ampersand => Ok("and"),
-            CalcOperatorType::Pipe => Ok("or"),
-            CalcOperatorType::Tild => Ok("not"),
-            _ => Err(CalcNodeError::OperatorMethodBindingError(*self)),
-        }
+        self.spec()
+            .binding
+            .ok_or(CalcNodeError::OperatorMethodBindingError(*self))
     }
 
     pub fn is_postfix(&self) -> Result<bool, CalcNodeError> {
-        match self {
-            CalcOperatorType::Plus
-            | CalcOperatorType::Minus
-            | CalcOperatorType::Asterisk
-            | CalcOperatorType::Slash
-            | CalcOperatorType::Caret
-            | CalcOperatorType::Modulus
-            | CalcOperatorType::Ampersand
-            | CalcOperatorType::Pipe => Ok(true),
-            CalcOperatorType::Tild => Ok(false),
-            _ => Err(CalcNodeError::OperatorMethodBindingError(*self)),
+        match self.fixity() {
+            CalcOperatorFixity::Infix | CalcOperatorFixity::InfixRight => Ok(true),
+            CalcOperatorFixity::Prefix | CalcOperatorFixity::Suffix => Ok(false),
+            CalcOperatorFixity::Punctuation => {
+                Err(CalcNodeError::OperatorMethodBindingError(*self))
+            }
         }
     }
+
+    /// Unary operators written after their operand, e.g. `5!` or `50%`.
+    pub fn is_suffix(&self) -> bool {
+        self.fixity() == CalcOperatorFixity::Suffix
+    }
+
+    /// `<<`, `>>` and `>>>`, the operators sharing the precedence of `<<`.
+    pub fn is_shift(&self) -> bool {
+        self.spec().precedence == CalcOperatorType::ShiftLeft.spec().precedence
+    }
+
+    pub fn verilog_precedence(&self) -> Option<u8> {
+        self.spec().verilog_precedence
+    }
+
+    /// Operator the parser turns into a call of `name`, e.g. `+` for `add`.
+    pub fn from_binding(name: &str) -> Option<CalcOperatorType> {
+        OPERATORS
+            .iter()
+            .find(|f| f.binding == Some(name))
+            .map(|f| f.operator)
+    }
+
+    /// Operators with a precedence pass grouped by level, tightest binding first.
+    pub fn by_precedence() -> Vec<Vec<CalcOperatorType>> {
+        let mut ops: Vec<&CalcOperatorSpec> = OPERATORS
+            .iter()
+            .filter(|f| f.precedence.is_some())
+            .collect();
+        ops.sort_by_key(|f| f.precedence);
+        let mut ans: Vec<Vec<CalcOperatorType>> = Vec::new();
+        let mut level = None;
+        for op in ops {
+            if level != op.precedence {
+                level = op.precedence;
+                ans.push(Vec::new());
+            }
+            let last = ans.last_mut().expect("A level was pushed");
+            if !last.contains(&op.operator) {
+                last.push(op.operator);
+            }
+        }
+        ans
+    }
+
+    /// Longest operator spelled by the graphemes at the start of `graphemes`, with the number
+    /// of graphemes it uses.
+    pub fn longest_match(graphemes: &[&str]) -> Option<(CalcOperatorType, usize)> {
+        let mut best: Option<(CalcOperatorType, usize)> = None;
+        let mut candidate = String::new();
+        for (len, g) in graphemes.iter().enumerate() {
+            candidate.push_str(g);
+            if !OPERATORS.iter().any(|f| f.symbol.starts_with(&candidate)) {
+                break;
+            }
+            if let Ok(op) = CalcOperatorType::try_from(candidate.as_str()) {
+                best = Some((op, len + 1));
+            }
+        }
+        best
+    }
 }
 
 impl TryFrom<&str> for CalcOperatorType {
     type Error = CalcNodeError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "+" => Ok(CalcOperatorType::Plus),
-            "-" => Ok(CalcOperatorType::Minus),
-            "*" => Ok(CalcOperatorType::Asterisk),
-            "/" => Ok(CalcOperatorType::Slash),
-            "(" => Ok(CalcOperatorType::ParenthesisOpen),
-            ")" => Ok(CalcOperatorType::ParenthesisClose),
-            "^" => Ok(CalcOperatorType::Caret),
-            "%" => Ok(CalcOperatorType::Modulus),
-            "," => Ok(CalcOperatorType::Comma),
-            "&" => Ok(CalcOperatorType::Ampersand),
-            "|" => Ok(CalcOperatorType::Pipe),
-            "~" => Ok(CalcOperatorType::Tild),
-            "=" => Ok(CalcOperatorType::Equals),
-            _ => Err(CalcNodeError::OperatorConversionError(value.to_string())),
-        }
+        OPERATORS
+            .iter()
+            .find(|f| f.symbol == value)
+            .map(|f| f.operator)
+            .ok_or_else(|| CalcNodeError::OperatorConversionError(value.to_string()))
     }
 }
 
 impl fmt::Display for CalcOperatorType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            " {} ",
-            match self {
-                CalcOperatorType::Plus => "+",
-                CalcOperatorType::Minus => "-",
-                CalcOperatorType::Asterisk => "*",
-                CalcOperatorType::Slash => "/",
-                CalcOperatorType::ParenthesisOpen => "(",
-                CalcOperatorType::ParenthesisClose => ")",
-                CalcOperatorType::Caret => "^",
-                CalcOperatorType::Modulus => "%",
-                CalcOperatorType::Comma => ",",
-                CalcOperatorType::Ampersand => "&",
-                CalcOperatorType::Pipe => "|",
-                CalcOperatorType::Tild => "~",
-                CalcOperatorType::Equals => "=",
-            }
-        )
+        write!(f, " {} ", self.symbol())
     }
 }
 
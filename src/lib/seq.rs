@@ -0,0 +1,82 @@
+use super::{format::parse_prefixed, funcs::check_verilog_operators, node::CalcNode};
+
+const USAGE: &str = "Usage: reg <name> <= <expr> on <clock> [reset <signal> [= <value>]]";
+
+/// Whether `text` is a plain name, the kind a register, clock or state is called.
+pub fn is_name(text: &str) -> bool {
+    let text = text.trim();
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Register declared with `reg q <= d on clk`, loading `d` on every rising edge of `clk`.
+/// Reading `q` in an expression gives the value loaded at the last edge. With
+/// `reset rst = 5` the register loads 5 instead on an edge where `rst` is high. A register
+/// wider than one bit is declared as a bus with `:bus` as well.
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub name: String,
+    /// Expression of the next value, evaluated when a module using the register is emitted.
+    pub next: CalcNode,
+    pub clock: String,
+    /// Synchronous, active high reset signal and the value it loads.
+    pub reset: Option<(String, u64)>,
+}
+
+impl Register {
+    /// Whether `decl`, without its leading `reg`, is shaped like a declaration,
+    /// `q <= d on clk`. Other lines starting with `reg` are expressions.
+    pub fn is_declaration(decl: &str) -> bool {
+        decl.split_once("<=")
+            .is_some_and(|(name, rest)| is_name(name) && rest.contains(" on "))
+    }
+}
+
+impl TryFrom<&str> for Register {
+    type Error = String;
+
+    /// Parses a declaration without its leading `reg`.
+    fn try_from(decl: &str) -> Result<Self, Self::Error> {
+        let (name, rest) = decl.split_once("<=").ok_or_else(|| USAGE.to_string())?;
+        let (expr, clocking) = rest.rsplit_once(" on ").ok_or_else(|| USAGE.to_string())?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(USAGE.to_string());
+        }
+        let next = crate::parse(expr.trim())
+            .ok_or_else(|| format!("Expected an expression for {}", name))?;
+        check_verilog_operators(&next)?;
+
+        let (clock, reset) = match clocking.split_once(" reset ") {
+            Some((clock, reset)) => (clock, Some(reset)),
+            None => (clocking, None),
+        };
+        let clock = clock.trim();
+        if clock.is_empty() || clock.contains(char::is_whitespace) {
+            return Err(USAGE.to_string());
+        }
+        let reset = match reset {
+            None => None,
+            Some(x) => {
+                let (signal, value) = x.split_once('=').unwrap_or((x, "0"));
+                let value = value.trim();
+                let value = value
+                    .parse::<u64>()
+                    .ok()
+                    .or_else(|| parse_prefixed(value).map(|f| f as u64))
+                    .ok_or_else(|| format!("Expected a reset value, got {}", value))?;
+                let signal = signal.trim();
+                if signal.is_empty() || signal.contains(char::is_whitespace) {
+                    return Err(USAGE.to_string());
+                }
+                Some((signal.to_string(), value))
+            }
+        };
+        Ok(Register {
+            name: name.to_string(),
+            next,
+            clock: clock.to_string(),
+            reset,
+        })
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::{
+    bus::{bit_name, group_bits},
+    format::parse_prefixed,
+    netlist::Netlist,
+};
+
+fn parse_value(name: &str, text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    text.parse::<u64>()
+        .ok()
+        .or_else(|| parse_prefixed(text).map(|f| f as u64))
+        .ok_or_else(|| format!("Expected a number for {}, got {}", name, text))
+}
+
+/// Sets the inputs named in `pairs`. A bus takes a number whose bits go to its bits, least
+/// significant first.
+pub fn assign_inputs(
+    netlist: &Netlist,
+    pairs: &[(&str, &str)],
+) -> Result<HashMap<String, bool>, String> {
+    let inputs: Vec<&str> = netlist.inputs.iter().map(|f| f.as_str()).collect();
+    let groups = group_bits(&inputs);
+    let mut ans = HashMap::new();
+    for (name, text) in pairs {
+        let value = parse_value(name, text)?;
+        match groups.iter().find(|f| f.0 == *name) {
+            None => return Err(format!("{} is not an input of {}", name, netlist.name)),
+            Some((_, None)) if value > 1 => {
+                return Err(format!("{} is one bit wide, got {}", name, value))
+            }
+            Some((_, None)) => {
+                ans.insert(name.to_string(), value == 1);
+            }
+            Some((_, Some(r))) => {
+                if r.width() < 64 && value >> r.width() != 0 {
+                    return Err(format!("{} doesn't fit in {}{}", value, name, r));
+                }
+                for bit in r.bits() {
+                    ans.insert(bit_name(name, bit), (value >> (bit - r.lsb)) & 1 == 1);
+                }
+            }
+        }
+    }
+    Ok(ans)
+}
+
+/// Output ports with their values, the bits of a bus gathered into one sized literal.
+fn output_values(netlist: &Netlist, values: &[bool]) -> Vec<(String, String)> {
+    let names: Vec<&str> = netlist.outputs.iter().map(|f| f.0.as_str()).collect();
+    group_bits(&names)
+        .into_iter()
+        .map(|(name, range)| {
+            let value = match range {
+                None => (values[names.iter().position(|f| *f == name).unwrap()] as u8).to_string(),
+                Some(r) => {
+                    let bits: String = r
+                        .bits()
+                        .rev()
+                        .map(|bit| {
+                            let port = bit_name(name, bit);
+                            match names.iter().position(|f| *f == port) {
+                                Some(i) => (b'0' + values[i] as u8) as char,
+                                None => 'x',
+                            }
+                        })
+                        .collect();
+                    format!("{}'b{}", r.width(), bits)
+                }
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Value of every wire, then of every output, for one assignment of the inputs.
+pub fn simulate(netlist: &Netlist, inputs: &HashMap<String, bool>) -> Result<String, String> {
+    let wires = netlist.evaluate(inputs)?;
+    let mut sout = String::new();
+    for gate in &netlist.gates {
+        writeln!(
+            sout,
+            "{} = {}",
+            netlist.wire_name(gate.id),
+            wires[&gate.id] as u8
+        )
+        .expect("msg");
+    }
+    let values = netlist.output_values(inputs)?;
+    for (name, value) in output_values(netlist, &values) {
+        writeln!(sout, "{} = {}", name, value).expect("msg");
+    }
+    sout.pop();
+    Ok(sout)
+}
+
+/// Runs every row of a CSV whose header names inputs, giving the rows back with the outputs
+/// added as columns.
+pub fn run_vectors(netlist: &Netlist, csv: &str) -> Result<String, String> {
+    let mut lines = csv.lines().map(|f| f.trim()).filter(|f| !f.is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| "No header row in the vectors".to_string())?
+        .split(',')
+        .map(|f| f.trim())
+        .collect();
+
+    let names: Vec<&str> = netlist.outputs.iter().map(|f| f.0.as_str()).collect();
+    let outputs: Vec<&str> = group_bits(&names).into_iter().map(|f| f.0).collect();
+    let mut sout = format!("{},{}", header.join(","), outputs.join(","));
+    for (row, line) in lines.enumerate() {
+        let cells: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if cells.len() != header.len() {
+            return Err(format!(
+                "Row {} has {} values for {} inputs",
+                row + 1,
+                cells.len(),
+                header.len()
+            ));
+        }
+        let pairs: Vec<(&str, &str)> = header.iter().copied().zip(cells.iter().copied()).collect();
+        let inputs = assign_inputs(netlist, &pairs)?;
+        let values = netlist.output_values(&inputs)?;
+        let outs: Vec<String> = output_values(netlist, &values)
+            .into_iter()
+            .map(|f| f.1)
+            .collect();
+        write!(sout, "\n{},{}", cells.join(","), outs.join(",")).expect("msg");
+    }
+    Ok(sout)
+}
@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use super::netlist::{Netlist, Signal};
+
+/// Transistors of a static CMOS gate with `n` inputs, `None` for gates that aren't cells.
+pub fn transistors(kind: &str, n: usize) -> Option<usize> {
+    match kind {
+        "not" => Some(2),
+        "buf" => Some(4),
+        "nand" | "nor" => Some(2 * n),
+        "and" | "or" => Some(2 * n + 2),
+        "xor" | "xnor" => Some(12 * n.saturating_sub(1)),
+        _ => None,
+    }
+}
+
+/// Size and timing figures of a netlist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// Gate count by type.
+    pub gates: BTreeMap<String, usize>,
+    /// Gates on the longest path from an input to an output.
+    pub depth: usize,
+    /// Signals along that path, from the input to the output port.
+    pub critical_path: Vec<String>,
+    /// Gate inputs and output ports driven by each input and wire.
+    pub fanout: Vec<(String, usize)>,
+    pub transistors: usize,
+    /// Gates left out of `transistors`, such as shifts.
+    pub uncounted: usize,
+    /// Total cell area, for a netlist mapped onto a cell library.
+    pub area: Option<f64>,
+}
+
+impl Stats {
+    pub fn of(netlist: &Netlist) -> Stats {
+        let mut gates = BTreeMap::new();
+        let mut count = 0;
+        let mut uncounted = 0;
+        let mut area = None;
+        for gate in &netlist.gates {
+            *gates.entry(gate.kind.to_string()).or_insert(0) += 1;
+            if let Some(cell) = netlist.cell(&gate.kind) {
+                area = Some(area.unwrap_or(0.0) + cell.area);
+                continue;
+            }
+            match transistors(&gate.kind, gate.inputs.len()) {
+                Some(x) => count += x,
+                None => uncounted += 1,
+            }
+        }
+
+        //depth of every wire, with the input it is reached through on its longest path
+        let mut depth: HashMap<usize, (usize, Option<Signal>)> = HashMap::new();
+        let depth_of = |s: &Signal, depth: &HashMap<usize, (usize, Option<Signal>)>| match s {
+            Signal::Wire(x) => depth[x].0,
+            _ => 0,
+        };
+        for gate in &netlist.gates {
+            let deepest = gate.inputs.iter().rev().max_by_key(|f| depth_of(f, &depth));
+            let d = deepest.map(|f| depth_of(f, &depth)).unwrap_or(0);
+            depth.insert(gate.id, (d + 1, deepest.cloned()));
+        }
+        //paths end at the outputs and at the registers, a register driving its own port ends
+        //at the register
+        let ends: Vec<(&str, &Signal)> = netlist
+            .outputs
+            .iter()
+            .filter(|f| !netlist.is_register_output(f))
+            .map(|f| (f.0.as_str(), &f.1))
+            .chain(netlist.flops.iter().map(|f| (f.name.as_str(), &f.d)))
+            .collect();
+        //the first of the deepest ends
+        let deepest = ends.iter().rev().max_by_key(|f| depth_of(f.1, &depth));
+        let (output, signal) = match deepest {
+            Some(x) => (x.0.to_string(), Some(x.1.clone())),
+            None => (String::new(), None),
+        };
+        let mut critical_path = vec![output];
+        let mut at = signal;
+        while let Some(s) = at {
+            critical_path.push(netlist.name_of(&s));
+            at = match s {
+                Signal::Wire(x) => depth[&x].1.clone(),
+                _ => None,
+            };
+        }
+        critical_path.reverse();
+
+        let mut fanout: Vec<(String, usize)> = netlist
+            .inputs
+            .iter()
+            .map(|f| (f.to_string(), 0))
+            .chain(netlist.flops.iter().map(|f| (f.name.to_string(), 0)))
+            .chain(netlist.gates.iter().map(|f| (netlist.wire_name(f.id), 0)))
+            .collect();
+        let reads = netlist.gates.iter().flat_map(|f| &f.inputs);
+        for signal in reads.chain(netlist.sinks()) {
+            let name = netlist.name_of(signal);
+            if let Some(x) = fanout.iter_mut().find(|f| f.0 == name) {
+                x.1 += 1;
+            }
+        }
+
+        Stats {
+            gates,
+            depth: ends
+                .iter()
+                .map(|f| depth_of(f.1, &depth))
+                .max()
+                .unwrap_or(0),
+            critical_path,
+            fanout,
+            transistors: count,
+            uncounted,
+            area,
+        }
+    }
+
+    pub fn gate_count(&self) -> usize {
+        self.gates.values().sum()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kinds: Vec<String> = self
+            .gates
+            .iter()
+            .map(|(kind, n)| format!("{} {}", kind, n))
+            .collect();
+        if kinds.is_empty() {
+            writeln!(f, "gates: 0")?;
+        } else {
+            writeln!(f, "gates: {} ({})", self.gate_count(), kinds.join(", "))?;
+        }
+        writeln!(
+            f,
+            "depth: {}, {}",
+            self.depth,
+            self.critical_path.join(" -> ")
+        )?;
+        let fanout: Vec<String> = self
+            .fanout
+            .iter()
+            .map(|(name, n)| format!("{} {}", name, n))
+            .collect();
+        let max = self.fanout.iter().map(|f| f.1).max().unwrap_or(0);
+        writeln!(f, "fan-out: {} (max {})", fanout.join(", "), max)?;
+        if let Some(area) = self.area {
+            return write!(f, "area: {}", area);
+        }
+        write!(f, "transistors: {}", self.transistors)?;
+        if self.uncounted > 0 {
+            write!(f, ", not counting {} shift gates", self.uncounted)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::{
+    bus::{bit_name, group_bits, BusRange},
+    netlist::Netlist,
+    node::{CalcFunctionData, CalcNode},
+    truth::CalcEvaluator,
+};
+
+/// Most input bits a testbench drives every combination of, random vectors are used above it.
+pub const EXHAUSTIVE_LIMIT: usize = 10;
+
+/// Xorshift generator, so the random vectors of a testbench are the same on every run.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Verilog literal for the bits of a port, `None` for a plain wire.
+fn literal(name: &str, range: Option<BusRange>, values: &HashMap<String, bool>) -> String {
+    let bit = |x: &str| if values[x] { '1' } else { '0' };
+    match range {
+        None => format!("1'b{}", bit(name)),
+        Some(r) => {
+            let bits: String = r.bits().rev().map(|f| bit(&bit_name(name, f))).collect();
+            format!("{}'b{}", r.width(), bits)
+        }
+    }
+}
+
+fn declaration(kind: &str, name: &str, range: Option<BusRange>) -> String {
+    match range {
+        None => format!("{} {};", kind, name),
+        Some(r) => format!("{} {} {};", kind, r, name),
+    }
+}
+
+/// Register of the model a testbench checks a sequential module against.
+struct ModelRegister {
+    name: String,
+    /// Tree of the value loaded on every clock edge.
+    next: CalcNode,
+    /// Signal loading `initial` instead when high on an edge.
+    reset: Option<String>,
+    initial: bool,
+}
+
+/// `node` with every `prev` read from a register of the model, named after the id of the
+/// `prev`. The registers are added to `registers` the first time they are met.
+fn read_prevs(node: &CalcNode, registers: &mut Vec<ModelRegister>) -> CalcNode {
+    match node {
+        CalcNode::Function(x) if x.name == "prev" => {
+            let name = format!("prev({})", x.id);
+            if !registers.iter().any(|f| f.name == name) {
+                let next = read_prevs(&x.params[0], registers);
+                registers.push(ModelRegister {
+                    name: name.to_string(),
+                    next,
+                    reset: None,
+                    initial: false,
+                });
+            }
+            CalcNode::Text(name)
+        }
+        CalcNode::Function(x) => CalcNode::Function(CalcFunctionData {
+            name: x.name.to_string(),
+            params: x.params.iter().map(|f| read_prevs(f, registers)).collect(),
+            operator: x.operator,
+            brackets: x.brackets,
+            id: x.id,
+        }),
+        x => x.clone(),
+    }
+}
+
+/// Values the registers of the model load on an edge with the inputs of `assignment`.
+fn step(
+    registers: &[ModelRegister],
+    evaluator: &mut CalcEvaluator,
+    assignment: &HashMap<String, bool>,
+) -> Result<HashMap<String, bool>, String> {
+    registers
+        .iter()
+        .map(|f| {
+            let value = match &f.reset {
+                Some(x) if assignment[x] => f.initial,
+                _ => evaluator.evaluate(&f.next, assignment)?,
+            };
+            Ok((f.name.to_string(), value))
+        })
+        .collect()
+}
+
+/// Testbench for `netlist`: it drives every input combination, or `random_vectors` random ones
+/// past `EXHAUSTIVE_LIMIT` input bits, and stops with `$fatal` on the first output that
+/// differs from the value the calculate context's evaluator gives for the expression it was
+/// emitted from.
+///
+/// A module with registers is run for `random_vectors` random vectors whatever its width, a
+/// vector per clock cycle, every clock rising together after the outputs are checked. The registers start from their reset values, loaded through the
+/// hierarchy so registers without a reset aren't unknown, and a first cycle holds the resets
+/// high with the other inputs low. The expected values come from a model of the registers
+/// stepped alongside, loading the trees they were declared with.
+pub fn testbench(netlist: &Netlist, random_vectors: usize) -> Result<String, String> {
+    let mut clocks: Vec<&str> = Vec::new();
+    let mut resets: Vec<&str> = Vec::new();
+    for flop in &netlist.flops {
+        if !clocks.contains(&flop.clock.as_str()) {
+            clocks.push(&flop.clock);
+        }
+        match &flop.reset {
+            Some((reset, _)) if !resets.contains(&reset.as_str()) => resets.push(reset),
+            _ => {}
+        }
+    }
+    let input_names: Vec<&str> = netlist.inputs.iter().map(|f| f.as_str()).collect();
+    let driven_names: Vec<&str> = input_names
+        .iter()
+        .copied()
+        .filter(|f| !clocks.contains(f) && !resets.contains(f))
+        .collect();
+    let output_names: Vec<&str> = netlist.outputs.iter().map(|f| f.0.as_str()).collect();
+    let inputs = group_bits(&input_names);
+    let driven = group_bits(&driven_names);
+    let outputs = group_bits(&output_names);
+    //the expected values come from the trees before the passes, so a pass that broke the
+    //logic is caught rather than checked against itself
+    let mut registers = Vec::new();
+    let trees: Vec<_> = netlist
+        .outputs
+        .iter()
+        .map(|(name, _)| {
+            netlist
+                .references
+                .iter()
+                .find(|f| f.0 == *name)
+                .map(|f| (f.0.to_string(), read_prevs(&f.1, &mut registers)))
+                .ok_or_else(|| format!("{} has no expression to check it against", name))
+        })
+        .collect::<Result<_, String>>()?;
+    for flop in &netlist.flops {
+        if netlist.prevs.values().any(|f| *f == flop.name) {
+            continue;
+        }
+        let next = netlist
+            .register_references
+            .iter()
+            .find(|f| f.0 == flop.name)
+            .ok_or_else(|| format!("{} has no expression to check it against", flop.name))?;
+        let next = read_prevs(&next.1, &mut registers);
+        registers.push(ModelRegister {
+            name: flop.name.to_string(),
+            next,
+            reset: flop.reset.as_ref().map(|f| f.0.to_string()),
+            initial: flop.initial(),
+        });
+    }
+
+    let n = driven_names.len();
+    //a sequential module goes through the same combinations in many states
+    let vectors: Vec<Vec<bool>> = if n <= EXHAUSTIVE_LIMIT && netlist.flops.is_empty() {
+        (0..1u32 << n)
+            .map(|m| (0..n).map(|i| m & (1 << (n - 1 - i)) != 0).collect())
+            .collect()
+    } else {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        (0..random_vectors)
+            .map(|_| (0..n).map(|_| rng.next() >> 63 == 1).collect())
+            .collect()
+    };
+
+    let name = &netlist.name;
+    let mut sout = String::new();
+    writeln!(sout, "`timescale 1ns/1ps").expect("msg");
+    writeln!(sout, "module {}_tb;", name).expect("msg");
+    for (port, range) in &inputs {
+        writeln!(sout, "    {}", declaration("reg", port, *range)).expect("msg");
+    }
+    for (port, range) in &outputs {
+        writeln!(sout, "    {}", declaration("wire", port, *range)).expect("msg");
+    }
+    let connections: Vec<String> = inputs
+        .iter()
+        .chain(&outputs)
+        .map(|(port, _)| format!(".{}({})", port, port))
+        .collect();
+    writeln!(sout, "    {} dut({});", name, connections.join(", ")).expect("msg");
+    writeln!(sout, "    initial begin").expect("msg");
+
+    let set = |names: &[&str], value: u8| -> String {
+        let sets: Vec<String> = names
+            .iter()
+            .map(|f| format!("{} = 1'b{};", f, value))
+            .collect();
+        sets.join(" ")
+    };
+    let pulse = format!("{} #1; {}", set(&clocks, 1), set(&clocks, 0));
+    let mut evaluator = CalcEvaluator::default();
+    let mut state: HashMap<String, bool> = registers
+        .iter()
+        .map(|f| (f.name.to_string(), f.initial))
+        .collect();
+    if !netlist.flops.is_empty() {
+        writeln!(sout, "        {}", set(&clocks, 0)).expect("msg");
+        for flop in &netlist.flops {
+            let value = flop.initial() as u8;
+            writeln!(sout, "        dut.{} = 1'b{};", flop.name, value).expect("msg");
+        }
+    }
+    if !resets.is_empty() {
+        let mut assignment = state.clone();
+        assignment.extend(driven_names.iter().map(|f| (f.to_string(), false)));
+        assignment.extend(clocks.iter().map(|f| (f.to_string(), false)));
+        assignment.extend(resets.iter().map(|f| (f.to_string(), true)));
+        let drives: Vec<String> = driven
+            .iter()
+            .map(|(port, range)| format!("{} = {};", port, literal(port, *range, &assignment)))
+            .collect();
+        let drives = std::iter::once(set(&resets, 1)).chain(drives);
+        writeln!(sout, "        {}", drives.collect::<Vec<_>>().join(" ")).expect("msg");
+        writeln!(sout, "        #1; {}", pulse).expect("msg");
+        writeln!(sout, "        {}", set(&resets, 0)).expect("msg");
+        state = step(&registers, &mut evaluator, &assignment)?;
+    }
+
+    for (cycle, vector) in vectors.iter().enumerate() {
+        let mut values: HashMap<String, bool> = driven_names
+            .iter()
+            .map(|f| f.to_string())
+            .zip(vector.iter().copied())
+            .collect();
+        let literals: Vec<(&str, String)> = driven
+            .iter()
+            .map(|(port, range)| (*port, literal(port, *range, &values)))
+            .collect();
+        let drives: Vec<String> = literals
+            .iter()
+            .map(|(port, x)| format!("{} = {};", port, x))
+            .collect();
+        let mut label: Vec<String> = literals
+            .iter()
+            .map(|(port, x)| format!("{}={}", port, x))
+            .collect();
+        if !netlist.flops.is_empty() {
+            label.insert(0, format!("cycle {}", cycle));
+        }
+        if !drives.is_empty() {
+            writeln!(sout, "        {}", drives.join(" ")).expect("msg");
+        }
+        writeln!(sout, "        #1;").expect("msg");
+
+        let mut assignment = values.clone();
+        assignment.extend(clocks.iter().chain(&resets).map(|f| (f.to_string(), false)));
+        assignment.extend(state.iter().map(|(k, v)| (k.to_string(), *v)));
+        for (output, tree) in &trees {
+            values.insert(output.to_string(), evaluator.evaluate(tree, &assignment)?);
+        }
+        for (port, range) in &outputs {
+            let expected = literal(port, *range, &values);
+            writeln!(
+                sout,
+                "        if ({} !== {}) $fatal(1, \"{}: {} is %b, expected {}\", {});",
+                port,
+                expected,
+                label.join(" "),
+                port,
+                expected,
+                port
+            )
+            .expect("msg");
+        }
+        if !netlist.flops.is_empty() {
+            writeln!(sout, "        {}", pulse).expect("msg");
+            state = step(&registers, &mut evaluator, &assignment)?;
+        }
+    }
+
+    writeln!(
+        sout,
+        "        $display(\"{}: {} vectors passed\");",
+        name,
+        vectors.len()
+    )
+    .expect("msg");
+    writeln!(sout, "        $finish;").expect("msg");
+    writeln!(sout, "    end").expect("msg");
+    sout.push_str("endmodule");
+    Ok(sout)
+}
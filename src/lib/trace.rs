@@ -0,0 +1,48 @@
+use std::cell::{Cell, RefCell};
+
+/// Destination for diagnostic output. Results never go through here, only the parser's and
+/// emitters' internals.
+pub trait Tracer {
+    fn trace(&self, message: &str);
+}
+
+/// Writes diagnostics to stderr so they stay out of piped results.
+pub struct StderrTracer;
+
+impl Tracer for StderrTracer {
+    fn trace(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+//kept per thread, so tests running side by side each see only their own diagnostics
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static TRACER: RefCell<Option<Box<dyn Tracer>>> = const { RefCell::new(None) };
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|f| f.set(enabled));
+}
+
+pub fn enabled() -> bool {
+    ENABLED.with(|f| f.get())
+}
+
+/// Installs the tracer that receives messages. Messages are dropped until one is set.
+pub fn set_tracer(tracer: Box<dyn Tracer>) {
+    TRACER.with(|f| *f.borrow_mut() = Some(tracer));
+}
+
+/// Emits the message built by `message` when tracing is enabled. The message is only built
+/// when it will be shown.
+pub fn trace(message: impl FnOnce() -> String) {
+    if !enabled() {
+        return;
+    }
+    TRACER.with(|f| {
+        if let Some(t) = f.borrow().as_ref() {
+            t.trace(&message());
+        }
+    });
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::{
+    context::{ContextManager, ContextType},
+    minimize::INPUT_LIMIT,
+    netlist::gate_value,
+    node::{CalcFunctionData, CalcNode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableStyle {
+    Aligned,
+    Csv,
+    Markdown,
+}
+
+impl TryFrom<&str> for TableStyle {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "table" => Ok(TableStyle::Aligned),
+            "csv" => Ok(TableStyle::Csv),
+            "md" | "markdown" => Ok(TableStyle::Markdown),
+            _ => Err(format!("Unknown table style: {}", value)),
+        }
+    }
+}
+
+/// Text leaves of `node`, in order of first use.
+pub fn inputs_of(node: &CalcNode) -> Vec<String> {
+    fn visit(node: &CalcNode, ans: &mut Vec<String>) {
+        match node {
+            CalcNode::Text(x) if !ans.contains(x) => ans.push(x.to_string()),
+            CalcNode::Function(x) => x.params.iter().for_each(|f| visit(f, ans)),
+            _ => {}
+        }
+    }
+    let mut ans = Vec::new();
+    visit(node, &mut ans);
+    ans
+}
+
+fn substitute(node: &CalcNode, assignment: &HashMap<String, bool>) -> CalcNode {
+    match node {
+        CalcNode::Text(x) => match assignment.get(x) {
+            Some(v) => CalcNode::SingleValue(*v as u8 as f64),
+            None => node.clone(),
+        },
+        CalcNode::Function(x) => CalcNode::Function(CalcFunctionData {
+            params: x.params.iter().map(|f| substitute(f, assignment)).collect(),
+            ..x.clone()
+        }),
+        _ => node.clone(),
+    }
+}
+
+/// Evaluates gate trees a bit at a time, the gates like the netlist does and anything else
+/// with the calculate context's built-ins.
+pub struct CalcEvaluator {
+    ctx: ContextManager,
+}
+
+impl Default for CalcEvaluator {
+    fn default() -> Self {
+        let mut ctx = ContextManager::new();
+        ctx.push_stack_frame(ContextType::Calculate);
+        CalcEvaluator { ctx }
+    }
+}
+
+impl CalcEvaluator {
+    /// Value of the gate tree `node` with its inputs set by `assignment`.
+    pub fn evaluate(
+        &mut self,
+        node: &CalcNode,
+        assignment: &HashMap<String, bool>,
+    ) -> Result<bool, String> {
+        match self.value(node, assignment, &mut HashMap::new()) {
+            CalcNode::SingleValue(x) => Ok((x as i64) & 1 == 1),
+            x => Err(format!("{:#} is not a boolean expression", x)),
+        }
+    }
+
+    /// Evaluates `node` a gate at a time, the gates shared in the tree are evaluated once.
+    /// Parsed functions all have id 0 and aren't remembered.
+    fn value(
+        &mut self,
+        node: &CalcNode,
+        assignment: &HashMap<String, bool>,
+        memo: &mut HashMap<usize, CalcNode>,
+    ) -> CalcNode {
+        match node {
+            CalcNode::Function(x) => {
+                if let Some(value) = memo.get(&x.id) {
+                    return value.clone();
+                }
+                let params: Vec<CalcNode> = x
+                    .params
+                    .iter()
+                    .map(|f| self.value(f, assignment, memo))
+                    .collect();
+                let bits: Option<Vec<u64>> = params
+                    .iter()
+                    .map(|f| match f {
+                        CalcNode::SingleValue(x) => Some(*x as u64 & 1),
+                        _ => None,
+                    })
+                    .collect();
+                //gates on wires of one bit, the calculator's `~` works on 64 and loses bit 0
+                //in the float it gives
+                if let Some(Ok(value)) = bits.map(|f| gate_value(&x.name, &f)) {
+                    let value = CalcNode::SingleValue((value & 1) as f64);
+                    if x.id != 0 {
+                        memo.insert(x.id, value.clone());
+                    }
+                    return value;
+                }
+                //not `..x.clone()`, that would copy the whole subtree for every gate
+                let value = self.ctx.eval(&CalcNode::Function(CalcFunctionData {
+                    name: x.name.to_string(),
+                    params,
+                    operator: x.operator,
+                    brackets: x.brackets,
+                    id: x.id,
+                }));
+                if x.id != 0 {
+                    memo.insert(x.id, value.clone());
+                }
+                value
+            }
+            _ => substitute(node, assignment),
+        }
+    }
+}
+
+/// Every assignment of the inputs of a gate tree with the value it gives.
+pub struct TruthTable {
+    pub inputs: Vec<String>,
+    pub rows: Vec<(Vec<bool>, bool)>,
+}
+
+impl TruthTable {
+    pub fn build(node: &CalcNode) -> Result<TruthTable, String> {
+        let inputs = inputs_of(node);
+        let n = inputs.len();
+        if n > INPUT_LIMIT {
+            return Err(format!(
+                "{} inputs is more than the {} a truth table is built for",
+                n, INPUT_LIMIT
+            ));
+        }
+        let mut evaluator = CalcEvaluator::default();
+        let mut rows = Vec::new();
+        for m in 0..1u32 << n {
+            let values: Vec<bool> = (0..n).map(|i| m & (1 << (n - 1 - i)) != 0).collect();
+            let assignment = inputs.iter().cloned().zip(values.iter().copied()).collect();
+            let value = evaluator.evaluate(node, &assignment)?;
+            rows.push((values, value));
+        }
+        Ok(TruthTable { inputs, rows })
+    }
+
+    pub fn classification(&self) -> &'static str {
+        if self.rows.iter().all(|f| f.1) {
+            "tautology"
+        } else if self.rows.iter().all(|f| !f.1) {
+            "contradiction"
+        } else {
+            "satisfiable"
+        }
+    }
+
+    pub fn render(&self, style: TableStyle, output: &str) -> String {
+        let mut header: Vec<&str> = self.inputs.iter().map(|f| f.as_str()).collect();
+        header.push(output);
+        let widths: Vec<usize> = header.iter().map(|f| f.len()).collect();
+        let bit = |x: bool| if x { "1" } else { "0" };
+
+        let mut sout = String::new();
+        let mut line = |cells: Vec<&str>| {
+            let row = match style {
+                TableStyle::Csv => cells.join(","),
+                TableStyle::Markdown => format!("| {} |", cells.join(" | ")),
+                TableStyle::Aligned => {
+                    let padded: Vec<String> = cells
+                        .iter()
+                        .zip(&widths)
+                        .map(|(c, w)| format!("{:>1$}", c, w))
+                        .collect();
+                    let (ins, out) = padded.split_at(padded.len() - 1);
+                    format!("{} | {}", ins.join(" "), out[0])
+                }
+            };
+            writeln!(sout, "{}", row.trim_start()).expect("msg");
+        };
+
+        line(header.clone());
+        if style == TableStyle::Markdown {
+            line(widths.iter().map(|_| "---").collect());
+        }
+        for (values, value) in &self.rows {
+            let mut cells: Vec<&str> = values.iter().map(|f| bit(*f)).collect();
+            cells.push(bit(*value));
+            line(cells);
+        }
+
+        let true_rows = self.rows.iter().filter(|f| f.1).count();
+        write!(
+            sout,
+            "{} ({} of {} rows true)",
+            self.classification(),
+            true_rows,
+            self.rows.len()
+        )
+        .expect("msg");
+        sout
+    }
+}
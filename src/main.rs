@@ -1,19 +1,26 @@
 use lib::node::CalcUserFunctionData;
-use std::convert::TryFrom;
 use std::io;
 use unicode_segmentation::UnicodeSegmentation;
 
 mod lib;
 mod test;
 
+use lib::command::run_command;
 use lib::context::*;
-use lib::node::{CalcFunctionData, CalcNode, CalcOperatorType};
+use lib::format::parse_prefixed;
+use lib::funcs::check_verilog_operators;
+use lib::node::{CalcFunctionData, CalcNode, CalcOperatorFixity, CalcOperatorType};
+use lib::seq::Register;
+use lib::trace;
 
 fn parse_buffer(buffer: &str, next_operator: Option<CalcOperatorType>) -> CalcNode {
     let entity = buffer.parse::<f64>();
     if let Ok(x) = entity {
         return CalcNode::SingleValue(x);
     }
+    if let Some(x) = parse_prefixed(buffer) {
+        return CalcNode::SingleValue(x);
+    }
     if let Some(CalcOperatorType::ParenthesisOpen) = next_operator {
         return CalcNode::Function(CalcFunctionData::new(buffer));
     }
@@ -149,18 +156,34 @@ fn apply_equals(slice: &mut [Option<CalcNode>]) {
     }
 }
 
-fn apply_precedence_unary(slice: &mut [Option<CalcNode>], operator: CalcOperatorType) {
-    let name = operator.get_function_bindings().unwrap();
+fn apply_precedence_unary(slice: &mut [Option<CalcNode>], operators: &[CalcOperatorType]) {
     for i in 0..slice.len() {
         let t = &slice[i];
         match t {
-            Some(CalcNode::Operator(op)) if *op == operator => {
-                let mut y = CalcFunctionData::new(name);
+            Some(CalcNode::Operator(op)) if operators.contains(op) => {
+                let mut y = CalcFunctionData::new(op.get_function_bindings().unwrap());
 
-                let item_index = find_first_ele_in_direction(slice, i, true);
+                if let Some(item) = take_operand(slice, i) {
+                    y.push_param(item);
+                }
+                slice[i] = Some(CalcNode::Function(y));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn apply_precedence_suffix(slice: &mut [Option<CalcNode>], operators: &[CalcOperatorType]) {
+    for i in 0..slice.len() {
+        let t = &slice[i];
+        match t {
+            Some(CalcNode::Operator(op)) if operators.contains(op) => {
+                let mut y = CalcFunctionData::new(op.get_function_bindings().unwrap());
+
+                let item_index = find_first_ele_in_direction(slice, i, false);
 
                 if let Some(x) = item_index {
-                    let item = (&mut slice[x]).take().unwrap();
+                    let item = slice[x].take().unwrap();
                     y.push_param(item);
                 }
                 slice[i] = Some(CalcNode::Function(y));
@@ -170,13 +193,94 @@ fn apply_precedence_unary(slice: &mut [Option<CalcNode>], operator: CalcOperator
     }
 }
 
-fn apply_precedence_binary(slice: &mut [Option<CalcNode>], operator: CalcOperatorType) {
-    let name = operator.get_function_bindings().unwrap();
+//a `%` with no operand after it (end of input or a binary operator) is a percentage, not a
+//modulus. A `-` or `~` after it starts the right operand, as in `10 % -3`
+fn mark_percent(slice: &mut [Option<CalcNode>]) {
     for i in 0..slice.len() {
+        if let Some(CalcNode::Operator(CalcOperatorType::Modulus)) = slice[i] {
+            let next = find_first_ele_in_direction(slice, i, true);
+            let is_suffix = match next {
+                None => true,
+                Some(n) => matches!(
+                    slice[n],
+                    Some(CalcNode::Operator(op)) if op != CalcOperatorType::Minus
+                        && op.fixity() != CalcOperatorFixity::Prefix
+                ),
+            };
+            if is_suffix {
+                slice[i] = Some(CalcNode::Operator(CalcOperatorType::Percent));
+            }
+        }
+    }
+}
+
+//takes the element after `i`, negated when a `-` comes first, so `2 ^ -3` and `~-a` read the
+//`-` as part of the operand
+fn take_operand(slice: &mut [Option<CalcNode>], i: usize) -> Option<CalcNode> {
+    let x = find_first_ele_in_direction(slice, i, true)?;
+    if let Some(CalcNode::Operator(CalcOperatorType::Minus)) = slice[x] {
+        let mut y = CalcFunctionData::new("negate");
+        if let Some(item) = take_operand(slice, x) {
+            y.push_param(item);
+        }
+        slice[x] = None;
+        return Some(CalcNode::Function(y));
+    }
+    slice[x].take()
+}
+
+//a `-` straight after another operator negates the element after it, e.g. `2 * -3`. It runs
+//after `^` and the suffixes, so `2 * -3 ^ 2` is `2 * -(3 ^ 2)` like a leading `-3 ^ 2`.
+//Right to left, so in `2 * - -3` the first `-` negates the second.
+fn apply_negation(slice: &mut [Option<CalcNode>]) {
+    for i in (0..slice.len()).rev() {
+        if let Some(CalcNode::Operator(CalcOperatorType::Minus)) = slice[i] {
+            let after_operator = match find_first_ele_in_direction(slice, i, false) {
+                Some(p) => matches!(slice[p], Some(CalcNode::Operator(op)) if !op.is_suffix()),
+                None => false,
+            };
+            let next = find_first_ele_in_direction(slice, i, true);
+            if let (true, Some(n)) = (after_operator, next) {
+                if !matches!(slice[n], Some(CalcNode::Operator(_))) {
+                    let mut y = CalcFunctionData::new("negate");
+                    y.push_param(slice[n].take().unwrap());
+                    slice[i] = Some(CalcNode::Function(y));
+                }
+            }
+        }
+    }
+}
+
+fn apply_precedence_binary_right(slice: &mut [Option<CalcNode>], operators: &[CalcOperatorType]) {
+    for i in (0..slice.len()).rev() {
         let t = &slice[i];
         match t {
-            Some(CalcNode::Operator(op)) if *op == operator => {
-                let mut y = CalcFunctionData::new(name);
+            Some(CalcNode::Operator(op)) if operators.contains(op) => {
+                let mut y = CalcFunctionData::new(op.get_function_bindings().unwrap());
+
+                let item_index = find_first_ele_in_direction(slice, i, false);
+
+                if let Some(x) = item_index {
+                    let item = slice[x].take().unwrap();
+                    y.push_param(item);
+                }
+
+                if let Some(item) = take_operand(slice, i) {
+                    y.push_param(item);
+                }
+                slice[i] = Some(CalcNode::Function(y));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn apply_precedence_binary(slice: &mut [Option<CalcNode>], operators: &[CalcOperatorType]) {
+    for i in 0..slice.len() {
+        let t = &slice[i];
+        match t {
+            Some(CalcNode::Operator(op)) if operators.contains(op) => {
+                let mut y = CalcFunctionData::new(op.get_function_bindings().unwrap());
 
                 let item_index = find_first_ele_in_direction(slice, i, false);
 
@@ -240,15 +344,22 @@ fn apply_precedence_overall(slice: &mut [Option<CalcNode>]) -> usize {
 
     let bracket_index = find_close_bracket(slice).unwrap_or(slice.len());
 
-    apply_precedence_unary(&mut slice[..bracket_index], CalcOperatorType::Tild);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Caret);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Slash);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Asterisk);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Modulus);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Minus);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Plus);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Ampersand);
-    apply_precedence_binary(&mut slice[..bracket_index], CalcOperatorType::Pipe);
+    mark_percent(&mut slice[..bracket_index]);
+    //operators sharing a level group in a single pass, e.g. `16 >> 2 << 1` is `(16 >> 2) << 1`
+    for level in CalcOperatorType::by_precedence() {
+        let part = &mut slice[..bracket_index];
+        match level[0].fixity() {
+            CalcOperatorFixity::Prefix => apply_precedence_unary(part, &level),
+            CalcOperatorFixity::Suffix => apply_precedence_suffix(part, &level),
+            CalcOperatorFixity::Infix => apply_precedence_binary(part, &level),
+            CalcOperatorFixity::InfixRight => apply_precedence_binary_right(part, &level),
+            CalcOperatorFixity::Punctuation => {}
+        }
+        //negation is the prefix level right below `^`
+        if level.contains(&CalcOperatorType::Caret) {
+            apply_negation(part);
+        }
+    }
 
     apply_equals(&mut slice[..bracket_index]);
 
@@ -265,21 +376,84 @@ fn eval(
         //treat as context command
         // let ctx: Context = buffer["context ".len()..].try_into().expect("No associated context found");
         let mode = &buffer["context ".len()..buffer.len() - 1];
+        if let Some(path) = mode.strip_prefix("verilog cells ") {
+            match ctx.push_cells_frame(path.trim()) {
+                Ok(()) => trace::trace(|| format!("Switched to context {}", mode)),
+                Err(e) => eprintln!("{}", e),
+            }
+            return CalcNode::NoValue;
+        }
         let c_type: ContextType = mode.try_into().expect("No Associated Context found");
         ctx.push_stack_frame(c_type);
-        println!("Parse Complete");
+        trace::trace(|| format!("Switched to context {}", mode));
         return CalcNode::NoValue;
     }
 
-    let x = UnicodeSegmentation::graphemes(&buffer[..], true).collect::<Vec<&str>>();
+    if !matches!(ctx.specific(), ContextType::Calculate) {
+        let decl = buffer.trim().strip_prefix("reg ");
+        if let Some(decl) = decl.filter(|f| Register::is_declaration(f)) {
+            match ctx.declare_register(decl) {
+                Ok(()) => trace::trace(|| format!("Declared register {}", decl.trim())),
+                Err(e) => eprintln!("{}", e),
+            }
+            return CalcNode::NoValue;
+        }
+        if let Some(ans) = ctx.fsm_line(buffer) {
+            if let Err(e) = ans {
+                eprintln!("{}", e);
+            }
+            return CalcNode::NoValue;
+        }
+    }
+
+    match parse_nodes(buffer, buffer_part_two, nodes) {
+        Some(i) => {
+            if !matches!(ctx.specific(), ContextType::Calculate) {
+                if let Err(e) = check_verilog_operators(&i) {
+                    eprintln!("{}", e);
+                    return CalcNode::NoValue;
+                }
+            }
+            ctx.eval(&i)
+        }
+        None => CalcNode::NoValue,
+    }
+}
+
+/// Parses one line into its expression tree, without evaluating it.
+pub fn parse(buffer: &str) -> Option<CalcNode> {
+    parse_nodes(buffer, &mut String::new(), &mut Vec::new())
+}
 
-    for y in x {
-        let possible_op = CalcOperatorType::try_from(y);
-        if possible_op.is_ok() {
+fn parse_nodes(
+    buffer: &str,
+    buffer_part_two: &mut String,
+    nodes: &mut Vec<Option<CalcNode>>,
+) -> Option<CalcNode> {
+    let x = UnicodeSegmentation::graphemes(buffer, true).collect::<Vec<&str>>();
+
+    let mut j = 0;
+    while j < x.len() {
+        let y = x[j];
+        //longer operators (e.g. `>>>`, `**`) take priority over single characters
+        let possible_op = CalcOperatorType::longest_match(&x[j..]);
+        if y == "{" || y == "}" {
+            //a concatenation `{a, b}` is read as the call `concat(a, b)`
+            if !buffer_part_two.is_empty() {
+                nodes.push(Some(parse_buffer(buffer_part_two, None)));
+                buffer_part_two.clear();
+            }
+            if y == "{" {
+                nodes.push(Some(CalcNode::Function(CalcFunctionData::new("concat"))));
+                nodes.push(Some(CalcNode::Operator(CalcOperatorType::ParenthesisOpen)));
+            } else {
+                nodes.push(Some(CalcNode::Operator(CalcOperatorType::ParenthesisClose)));
+            }
+        } else if let Some((opera, len)) = possible_op {
+            j += len - 1;
             //look at buffer now
-            let opera = possible_op.unwrap();
             if !buffer_part_two.is_empty() {
-                nodes.push(Some(parse_buffer(&buffer_part_two, Some(opera))));
+                nodes.push(Some(parse_buffer(buffer_part_two, Some(opera))));
                 buffer_part_two.clear();
             }
             //then pass the operator
@@ -287,23 +461,22 @@ fn eval(
         } else if !y.trim().is_empty() {
             buffer_part_two.push_str(y.trim());
         }
+        j += 1;
     }
     if !buffer_part_two.is_empty() {
-        nodes.push(Some(parse_buffer(&buffer_part_two, None)));
+        nodes.push(Some(parse_buffer(buffer_part_two, None)));
         buffer_part_two.clear();
     }
     apply_precedence_overall(nodes);
     // apply_precedence_rules(&mut nodes, CalcOperatorType::Plus, CalcOperatorType::Minus, "add", "minus");
 
-    println!("{:?}", nodes);
+    trace::trace(|| format!("{:?}", nodes));
 
-    for t in nodes {
-        if let Some(i) = t {
-            println!("Parse Complete");
-            return ctx.eval(i);
-        }
+    let ans = nodes.iter_mut().find_map(|f| f.take());
+    if ans.is_some() {
+        trace::trace(|| "Parse Complete".to_string());
     }
-    return CalcNode::NoValue;
+    ans
 }
 
 fn main() {
@@ -312,14 +485,30 @@ fn main() {
     let mut nodes = Vec::<Option<CalcNode>>::new();
     let mut ctxs = ContextManager::new();
     ctxs.push_stack_frame(ContextType::Calculate);
+    trace::set_tracer(Box::new(trace::StderrTracer));
+    if std::env::args()
+        .skip(1)
+        .any(|f| f == "--verbose" || f == "-v")
+    {
+        trace::set_enabled(true);
+    }
     loop {
-        io::stdin()
+        let read = io::stdin()
             .read_line(&mut buffer)
             .expect("Something went wrong");
+        if read == 0 {
+            break;
+        }
 
-        let ans = eval(&mut buffer, &mut buffer_part_two, &mut nodes, &mut ctxs);
+        if let Some(out) = run_command(&buffer, &mut ctxs) {
+            if !out.is_empty() {
+                println!("{}", out);
+            }
+        } else {
+            let ans = eval(&mut buffer, &mut buffer_part_two, &mut nodes, &mut ctxs);
 
-        ctxs.print_result(&ans);
+            ctxs.print_result(&ans);
+        }
 
         buffer.clear();
         buffer_part_two.clear();
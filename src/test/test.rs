@@ -23,6 +23,27 @@ fn evaluate_expr_calc(buffer: &str) -> crate::lib::node::CalcNode {
     return ans;
 }
 
+#[cfg(test)]
+fn evaluate_expr_in(
+    buffer: &str,
+    typ: crate::lib::context::ContextType,
+) -> (
+    crate::lib::node::CalcNode,
+    crate::lib::context::ContextManager,
+) {
+    use crate::{eval, lib::context::ContextManager};
+
+    let mut ctx = ContextManager::new();
+    ctx.push_stack_frame(typ);
+    let ans = eval(
+        &mut buffer.to_string(),
+        &mut String::new(),
+        &mut Vec::new(),
+        &mut ctx,
+    );
+    (ans, ctx)
+}
+
 #[test]
 #[cfg(test)]
 pub fn one() {
@@ -42,3 +63,1373 @@ pub fn two() {
         crate::lib::node::CalcNode::SingleValue((2.0 + 11.0) * 4.0)
     );
 }
+
+#[test]
+#[cfg(test)]
+pub fn caret_is_right_associative() {
+    use crate::lib::node::CalcNode;
+    assert_eq!(
+        evaluate_expr_calc("2 ^ 3 ^ 2"),
+        CalcNode::SingleValue(512.0)
+    );
+    assert_eq!(
+        evaluate_expr_calc("2 ** 3 ** 2"),
+        CalcNode::SingleValue(512.0)
+    );
+    assert_eq!(
+        evaluate_expr_calc("2 ** 3 * 2"),
+        CalcNode::SingleValue(16.0)
+    );
+    //a unary `-` binds looser than `^` wherever it appears
+    assert_eq!(evaluate_expr_calc("-3 ^ 2"), CalcNode::SingleValue(-9.0));
+    assert_eq!(
+        evaluate_expr_calc("2 * -3 ^ 2"),
+        CalcNode::SingleValue(-18.0)
+    );
+    assert_eq!(
+        evaluate_expr_calc("1 + -2 ^ 2"),
+        CalcNode::SingleValue(-3.0)
+    );
+    assert_eq!(evaluate_expr_calc("2 ^ -1"), CalcNode::SingleValue(0.5));
+
+    //`**` is exponentiation in Verilog, it isn't lowered to xor like `^`
+    use crate::lib::{command::run_command, context::ContextType, seq::Register};
+    let (ans, mut ctx) = evaluate_expr_in("a ** b", ContextType::Verilog);
+    assert_eq!(ans, CalcNode::NoValue);
+    assert_eq!(
+        run_command(":truth a ** b", &mut ctx).unwrap(),
+        "** is exponentiation in Verilog and has no gates, use ^ for xor"
+    );
+    assert!(Register::try_from("q <= q ** d on clk").is_err());
+}
+
+#[test]
+#[cfg(test)]
+pub fn factorial_and_percent() {
+    use crate::lib::node::CalcNode;
+    assert_eq!(evaluate_expr_calc("5!"), CalcNode::SingleValue(120.0));
+    assert_eq!(evaluate_expr_calc("2 ^ 3!"), CalcNode::SingleValue(64.0));
+    assert_eq!(evaluate_expr_calc("2 * -3!"), CalcNode::SingleValue(-12.0));
+    assert_eq!(evaluate_expr_calc("50% * 8"), CalcNode::SingleValue(4.0));
+    assert_eq!(evaluate_expr_calc("10 % 4"), CalcNode::SingleValue(2.0));
+    assert_eq!(evaluate_expr_calc("10 % -3"), CalcNode::SingleValue(1.0));
+    assert_eq!(evaluate_expr_calc("50% + 1"), CalcNode::SingleValue(1.5));
+    assert_eq!(
+        evaluate_expr_calc("1e10!"),
+        CalcNode::SingleValue(f64::INFINITY)
+    );
+    if let CalcNode::SingleValue(x) = evaluate_expr_calc("0.5!") {
+        assert!((x - std::f64::consts::PI.sqrt() / 2.0).abs() < 1e-9);
+    } else {
+        panic!("factorial of a non-integer should evaluate");
+    }
+}
+
+#[test]
+#[cfg(test)]
+pub fn shifts_and_rotates() {
+    use crate::lib::node::CalcNode;
+    assert_eq!(
+        evaluate_expr_calc("1 << 4 + 1"),
+        CalcNode::SingleValue(32.0)
+    );
+    assert_eq!(evaluate_expr_calc("256 >> 4"), CalcNode::SingleValue(16.0));
+    assert_eq!(
+        evaluate_expr_calc("(0 - 16) >>> 2"),
+        CalcNode::SingleValue(-4.0)
+    );
+    //the bitwise operators work on unsigned 64 bit integers
+    assert_eq!(
+        evaluate_expr_calc("~5"),
+        CalcNode::SingleValue(18446744073709551610u64 as f64)
+    );
+    assert_eq!(
+        evaluate_expr_calc("rotl(129, 1, 8)"),
+        CalcNode::SingleValue(3.0)
+    );
+    assert_eq!(
+        evaluate_expr_calc("rotr(3, 1, 8)"),
+        CalcNode::SingleValue(129.0)
+    );
+}
+
+#[test]
+#[cfg(test)]
+pub fn verilog_shift_is_an_assign() {
+    use crate::lib::context::ContextType;
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) << 2", ContextType::Verilog);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.contains("and(w_0, a, b);"));
+    assert!(out.contains("assign w_1 = w_0 << 2;"));
+
+    //rotl takes the same arguments as in the calculator
+    let (ans, mut ctx) = evaluate_expr_in("rotl(a, 1)", ContextType::Verilog);
+    assert!(ctx.emit_verilog(&ans).contains("assign w_0 = a;"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn operators_sharing_a_level_group_left_to_right() {
+    use crate::lib::node::CalcNode;
+    assert_eq!(
+        evaluate_expr_calc("16 >> 2 << 1"),
+        CalcNode::SingleValue(8.0)
+    );
+    assert_eq!(evaluate_expr_calc("7 % 3 * 2"), CalcNode::SingleValue(2.0));
+    assert_eq!(evaluate_expr_calc("3 > 2 < 1"), CalcNode::SingleValue(0.0));
+    assert_eq!(
+        evaluate_expr_calc("1 != 2 == 1"),
+        CalcNode::SingleValue(1.0)
+    );
+}
+
+#[test]
+#[cfg(test)]
+pub fn verilog_relations_are_gates() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (ans, mut ctx) = evaluate_expr_in("a == b", ContextType::Verilog);
+    assert!(ctx.emit_verilog(&ans).contains("xnor(w_0, a, b);"));
+    let (ans, mut ctx) = evaluate_expr_in("a < b -> c", ContextType::VerilogAssign);
+    assert!(ctx.emit_verilog(&ans).contains("assign y = ~(~a & b) | c;"));
+
+    run_command(":bus x[3:0]", &mut ctx);
+    run_command(":bus z[3:0]", &mut ctx);
+    let ans = ctx.eval(&crate::parse("x == z").unwrap());
+    assert_eq!(ctx.emit_verilog(&ans), "== of a bus is not supported");
+}
+
+#[test]
+#[cfg(test)]
+pub fn multi_character_operators() {
+    use crate::lib::node::{CalcNode, CalcOperatorType};
+    assert_eq!(
+        CalcOperatorType::longest_match(&[">", ">", ">", "1"]),
+        Some((CalcOperatorType::ArithmeticShiftRight, 3))
+    );
+    assert_eq!(
+        CalcOperatorType::longest_match(&["-", ">"]),
+        Some((CalcOperatorType::Arrow, 2))
+    );
+    assert_eq!(evaluate_expr_calc("1 + 2 <= 3"), CalcNode::SingleValue(1.0));
+    assert_eq!(evaluate_expr_calc("2 * 2 == 5"), CalcNode::SingleValue(0.0));
+    assert_eq!(
+        evaluate_expr_calc("1 && 0 || 1"),
+        CalcNode::SingleValue(1.0)
+    );
+    assert_eq!(evaluate_expr_calc("1 -> 0"), CalcNode::SingleValue(0.0));
+    assert_eq!(evaluate_expr_calc("5 != 4"), CalcNode::SingleValue(1.0));
+}
+
+#[test]
+#[cfg(test)]
+pub fn base_conversion() {
+    use crate::lib::{
+        command::run_command,
+        context::{ContextManager, ContextType},
+        format::IntegerFormat,
+        node::CalcNode,
+    };
+    assert_eq!(
+        evaluate_expr_calc("0xFF + 0b1010"),
+        CalcNode::SingleValue(265.0)
+    );
+    assert_eq!(evaluate_expr_calc("0o17"), CalcNode::SingleValue(15.0));
+    assert_eq!(
+        evaluate_expr_calc("to_base(255, 16)"),
+        CalcNode::Text("0xff".to_string())
+    );
+    assert_eq!(
+        evaluate_expr_calc("to_base(0 - 35, 36)"),
+        CalcNode::Text("-z".to_string())
+    );
+    assert_eq!(
+        evaluate_expr_calc("to_base(255, 40)"),
+        CalcNode::Text("Base must be between 2 and 36, got 40".to_string())
+    );
+
+    let mut ctx = ContextManager::new();
+    ctx.push_stack_frame(ContextType::Calculate);
+    assert_eq!(run_command(":format bin", &mut ctx), Some(String::new()));
+    assert_eq!(ctx.format.base, IntegerFormat::Binary);
+    assert_eq!(
+        ctx.get_top()
+            .format_result(&CalcNode::SingleValue(10.0), &ctx.format),
+        "0b1010"
+    );
+    assert!(run_command(":format base 40", &mut ctx)
+        .unwrap()
+        .starts_with("Base must be"));
+    assert_eq!(run_command("1 + 1", &mut ctx), None);
+}
+
+#[test]
+#[cfg(test)]
+pub fn number_formatting() {
+    use crate::lib::format::{Notation, NumberFormat};
+    let mut format = NumberFormat::default();
+    assert_eq!(format.render(46.0), "46");
+    assert_eq!(format.render(0.1 + 0.2), "0.30000000000000004");
+    assert_eq!(format.render(-0.000001), "-1e-6");
+
+    format.precision = Some(4);
+    assert_eq!(format.render(0.1 + 0.2), "0.3");
+    assert_eq!(format.render(1234567.0), "1.235e6");
+    format.trim = false;
+    assert_eq!(format.render(0.1 + 0.2), "0.3000");
+
+    format.notation = Notation::Engineering;
+    assert_eq!(format.render(0.000047), "47.00e-6");
+
+    format = NumberFormat::default();
+    format.notation = Notation::Fixed;
+    format.separators = true;
+    assert_eq!(format.render(-1234567.5), "-1,234,567.5");
+    format.notation = Notation::Scientific;
+    assert_eq!(format.render(1234.0), "1.234e3");
+}
+
+#[test]
+#[cfg(test)]
+pub fn results_are_shown_without_debug_wrapper() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (ans, mut ctx) = evaluate_expr_in("(2 + 11) * 4", ContextType::Calculate);
+    assert_eq!(ctx.get_top().format_result(&ans, &ctx.format), "52");
+    run_command(":separators on", &mut ctx);
+    run_command(":precision 3", &mut ctx);
+    let (ans, _) = evaluate_expr_in("1234.5 * 1000", ContextType::Calculate);
+    assert_eq!(ctx.get_top().format_result(&ans, &ctx.format), "1.23e6");
+    run_command(":format fixed", &mut ctx);
+    assert_eq!(ctx.get_top().format_result(&ans, &ctx.format), "1,230,000");
+}
+
+#[test]
+#[cfg(test)]
+pub fn diagnostics_go_through_the_tracer() {
+    use crate::lib::{
+        command::run_command,
+        context::ContextType,
+        trace::{self, Tracer},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    struct Capture(Rc<RefCell<Vec<String>>>);
+    impl Tracer for Capture {
+        fn trace(&self, message: &str) {
+            self.0.borrow_mut().push(message.to_string());
+        }
+    }
+
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    trace::set_tracer(Box::new(Capture(lines.clone())));
+    let (_, mut ctx) = evaluate_expr_in("1 + 1", ContextType::Calculate);
+    assert_eq!(
+        run_command(":debug on", &mut ctx),
+        Some("debug on".to_string())
+    );
+    evaluate_expr_calc("2 + 2");
+    run_command(":debug off", &mut ctx);
+    assert!(lines.borrow().iter().any(|f| f == "Parse Complete"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn verilog_emits_complete_modules() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (ans, mut ctx) = evaluate_expr_in("a & b", ContextType::Verilog);
+    assert_eq!(
+        ctx.emit_verilog(&ans),
+        "// gates: 1 before hash-consing, 1 after
+module top(input a, b, output y);
+    wire w_0;
+    and(w_0, a, b);
+    assign y = w_0;
+endmodule"
+    );
+
+    let ans = crate::eval(
+        &mut "carry = (a & b) | c".to_string(),
+        &mut String::new(),
+        &mut Vec::new(),
+        &mut ctx,
+    );
+    run_command(":name half", &mut ctx);
+    run_command(":ports c b a", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.contains("\nmodule half(input c, b, a, output carry);"));
+    assert!(out.contains("assign carry = w_"));
+
+    run_command(":ports x -> q", &mut ctx);
+    let (ans, _) = evaluate_expr_in("~x", ContextType::Verilog);
+    assert!(ctx
+        .emit_verilog(&ans)
+        .contains("\nmodule half(input x, output q);"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn multi_output_module_shares_gates() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (_, mut ctx) = evaluate_expr_in("sum = (a ^ b) ^ cin", ContextType::Verilog);
+    crate::eval(
+        &mut "cout = a & b | cin & (a ^ b)".to_string(),
+        &mut String::new(),
+        &mut Vec::new(),
+        &mut ctx,
+    );
+    let out = run_command(":module full_adder sum cout", &mut ctx).unwrap();
+    assert!(out.contains("\nmodule full_adder(input a, b, cin, output sum, cout);"));
+    assert_eq!(out.matches("xor(").count(), 2);
+    assert_eq!(out.matches("and(").count(), 2);
+    assert_eq!(out.matches(" or(").count(), 1);
+    assert!(out.contains("assign sum = w_"));
+    assert!(out.contains("assign cout = w_"));
+
+    assert_eq!(
+        run_command(":module adder nothing", &mut ctx),
+        Some("No definition found for nothing".to_string())
+    );
+}
+
+#[test]
+#[cfg(test)]
+pub fn hash_consing_merges_equal_gates() {
+    use crate::lib::context::ContextType;
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) | (b & a)", ContextType::Verilog);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.starts_with("// gates: 3 before hash-consing, 2 after\n"));
+    assert!(out.contains("or(w_1, w_0, w_0);"));
+
+    let (ans, mut ctx) = evaluate_expr_in("~(a | b) & ~(a | b)", ContextType::VerilogNand);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.starts_with("// gates: 10 before hash-consing, 6 after\n"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn quine_mccluskey_and_espresso_covers() {
+    use crate::lib::minimize::{espresso, quine_mccluskey, Cube};
+    //b'c' + cd' + a'bd
+    let minterms = [0, 1, 2, 5, 6, 7, 8, 9, 10, 14];
+    let exact = quine_mccluskey(4, &minterms);
+    assert_eq!(exact.len(), 3);
+    assert_eq!(exact.iter().map(|f| f.literals(4)).sum::<usize>(), 7);
+    for cover in [&exact, &espresso(4, &minterms)] {
+        for m in 0..16u32 {
+            assert_eq!(
+                cover.iter().any(|f| f.contains(m)),
+                minterms.contains(&m),
+                "minterm {}",
+                m
+            );
+        }
+    }
+    assert_eq!(
+        quine_mccluskey(2, &[0, 1, 2, 3]),
+        vec![Cube { bits: 0, dashes: 3 }]
+    );
+    assert!(quine_mccluskey(3, &[]).is_empty());
+
+    //ab + cd + ... + op, where a minterm at a time would take minutes
+    let minterms: Vec<u32> = (0..1u32 << 16)
+        .filter(|m| (0..8).any(|i| (m >> (2 * i)) & 3 == 3))
+        .collect();
+    let cover = espresso(16, &minterms);
+    assert_eq!(cover.len(), 8);
+    assert!(cover.iter().all(|f| f.literals(16) == 2));
+}
+
+#[test]
+#[cfg(test)]
+pub fn minimized_verilog_output() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) | (a & ~b)", ContextType::Verilog);
+    run_command(":minimize on", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.contains("// y: minimized to 1 product terms, 1 literals (quine-mccluskey)"));
+    assert!(out.contains("module top(input a, b, output y);"));
+    assert!(out.contains("assign y = a;"));
+    assert!(!out.contains("wire"));
+
+    let (ans, mut ctx) = evaluate_expr_in("(a | b) & (a | c)", ContextType::VerilogNand);
+    run_command(":minimize espresso", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.contains("2 product terms, 3 literals (espresso)"));
+    assert!(!out.contains(" and("));
+}
+
+#[test]
+#[cfg(test)]
+pub fn truth_tables() {
+    use crate::lib::{command::run_command, context::ContextType};
+    for context in [
+        ContextType::Verilog,
+        ContextType::VerilogNand,
+        ContextType::VerilogNor,
+    ] {
+        let (_, mut ctx) = evaluate_expr_in("0", context);
+        for expr in ["(a & ~b) | (~a & b)", "a ^ b"] {
+            let out = run_command(&format!(":truth {}", expr), &mut ctx).unwrap();
+            assert_eq!(
+                out,
+                "a b | y\n0 0 | 0\n0 1 | 1\n1 0 | 1\n1 1 | 0\nsatisfiable (2 of 4 rows true)"
+            );
+        }
+    }
+
+    let (_, mut ctx) = evaluate_expr_in("0", ContextType::Verilog);
+    let out = run_command(":truth csv a | ~a", &mut ctx).unwrap();
+    assert_eq!(out, "a,y\n0,1\n1,1\ntautology (2 of 2 rows true)");
+    let out = run_command(":truth md a & ~a", &mut ctx).unwrap();
+    assert!(out.starts_with("| a | y |\n| --- | --- |\n| 0 | 0 |"));
+    assert!(out.ends_with("contradiction (0 of 2 rows true)"));
+
+    let (_, mut ctx) = evaluate_expr_in("0", ContextType::Calculate);
+    assert!(run_command(":truth a & b", &mut ctx).is_some_and(|f| f.contains("verilog")));
+}
+
+#[test]
+#[cfg(test)]
+pub fn equivalence_checking() {
+    use crate::lib::{
+        command::run_command,
+        context::ContextType,
+        equiv::{check, check_with, Method},
+    };
+    let (_, mut ctx) = evaluate_expr_in("0", ContextType::Verilog);
+    let mut equiv = |args: &str| run_command(&format!(":equiv {}", args), &mut ctx).unwrap();
+    assert_eq!(
+        equiv("a & b, ~(~a | ~b)"),
+        "equivalent over 2 inputs (truth table)"
+    );
+    assert_eq!(
+        equiv("a ^ b, a | b"),
+        "not equivalent: a=1 b=1 gives 0 and 1"
+    );
+    assert_eq!(
+        equiv("[verilog nand] (a | b) & c, [verilog nor] (a & c) | (b & c)"),
+        "equivalent over 3 inputs (truth table)"
+    );
+    assert_eq!(
+        equiv("nand(a, b), a | b"),
+        "not equivalent: a=0 b=0 gives 1 and 0"
+    );
+
+    //past the truth table limit, the same answers come from the bdd
+    let vars: Vec<String> = (0..14).map(|f| format!("x{}", f)).collect();
+    let chain = |vars: &[String]| vars.join(" ^ ");
+    let mut reversed = vars.clone();
+    reversed.reverse();
+    let (lhs, _) = evaluate_expr_in(&chain(&vars), ContextType::Verilog);
+    let (rhs, _) = evaluate_expr_in(&chain(&reversed), ContextType::Verilog);
+    let ans = check(&lhs, &rhs).unwrap();
+    assert_eq!(ans.method, Method::Bdd);
+    assert!(ans.counterexample.is_none());
+
+    let (rhs, _) = evaluate_expr_in(&vars.join(" | "), ContextType::Verilog);
+    let ans = check(&lhs, &rhs).unwrap();
+    let (values, l, r) = ans.counterexample.clone().unwrap();
+    assert_ne!(l, r);
+    assert_eq!(values.len(), 14);
+
+    for (a, b) in [("a & ~b", "~(~a | b)"), ("a ^ b", "a ^ ~b")] {
+        let (lhs, _) = evaluate_expr_in(a, ContextType::Verilog);
+        let (rhs, _) = evaluate_expr_in(b, ContextType::Verilog);
+        assert_eq!(
+            check_with(&lhs, &rhs, Method::Bdd)
+                .unwrap()
+                .counterexample
+                .is_none(),
+            check_with(&lhs, &rhs, Method::TruthTable)
+                .unwrap()
+                .counterexample
+                .is_none()
+        );
+    }
+}
+
+#[test]
+#[cfg(test)]
+pub fn gate_contexts_preserve_verilog_semantics() {
+    use crate::lib::{
+        command::run_command,
+        context::{ContextManager, ContextType},
+        equiv::check,
+        funcs::assemble_map_veri,
+        node::{CalcFunctionData, CalcNode},
+    };
+    let lower = |typ: ContextType, name: &str| {
+        let arity = match name {
+            "not" => 1,
+            "rotl" | "rotr" => 3,
+            _ => 2,
+        };
+        let mut ctx = ContextManager::new();
+        ctx.push_stack_frame(typ);
+        let params = ["a", "b", "c"][..arity]
+            .iter()
+            .map(|f| CalcNode::Text(f.to_string()))
+            .collect();
+        ctx.get_built_in(name).is_some().then(|| {
+            ctx.eval(&CalcNode::Function(CalcFunctionData {
+                params,
+                ..CalcFunctionData::new(name)
+            }))
+        })
+    };
+    //every gate the lowered tree uses, apart from shifts which all contexts share
+    fn gates(node: &CalcNode, ans: &mut Vec<String>) {
+        if let CalcNode::Function(x) = node {
+            if !ans.contains(&x.name) {
+                ans.push(x.name.clone());
+            }
+            x.params.iter().for_each(|f| gates(f, ans));
+        }
+    }
+    for name in assemble_map_veri().keys() {
+        //concat and prev are only markers for the bus pass and the netlist
+        if name == "concat" || name == "prev" {
+            continue;
+        }
+        let reference = lower(ContextType::Verilog, name).unwrap();
+        for (typ, label, universal) in [
+            (ContextType::VerilogNand, "verilog nand", "nand"),
+            (ContextType::VerilogNor, "verilog nor", "nor"),
+        ] {
+            let rewritten = lower(typ, name).unwrap_or_else(|| panic!("{} in {}", name, label));
+            let ans = check(&reference, &rewritten).unwrap();
+            assert!(
+                ans.counterexample.is_none(),
+                "{} in {}: {}",
+                name,
+                label,
+                ans
+            );
+            let mut used = Vec::new();
+            gates(&rewritten, &mut used);
+            if !["shl", "shr", "ashr", "rotl", "rotr"].contains(&name.as_str()) {
+                assert_eq!(used, vec![universal], "{} in {}", name, label);
+            }
+        }
+    }
+
+    //nested operators lower the same way
+    let (_, mut ctx) = evaluate_expr_in("0", ContextType::Verilog);
+    for expr in [
+        "(a ^ b) ^ ~c",
+        "nand(a ^ b, nor(c, xnor(a, c)))",
+        "(a + b) * ~(a ^ c)",
+    ] {
+        for other in ["verilog nand", "verilog nor"] {
+            let out = run_command(
+                &format!(":equiv [verilog] {}, [{}] {}", expr, other, expr),
+                &mut ctx,
+            );
+            assert!(
+                out.unwrap().starts_with("equivalent over 3 inputs"),
+                "{} in {}",
+                expr,
+                other
+            );
+        }
+    }
+}
+
+#[test]
+#[cfg(test)]
+pub fn buses_expand_per_bit() {
+    use crate::lib::{
+        command::run_command,
+        context::{ContextManager, ContextType},
+    };
+    let (_, mut ctx) = evaluate_expr_in("0", ContextType::Verilog);
+    run_command(":bus a[3:0] b[3:0] c[1:0]", &mut ctx);
+    assert_eq!(
+        run_command(":bus", &mut ctx).unwrap(),
+        "a[3:0] b[3:0] c[1:0]"
+    );
+    let emit = |expr: &str, ctx: &mut ContextManager| {
+        let ans = ctx.eval(&crate::parse(expr).unwrap());
+        ctx.emit_verilog(&ans)
+    };
+
+    let out = emit("a & b", &mut ctx);
+    assert!(out.contains("module top(input [3:0] a, b, output [3:0] y);"));
+    assert!(out.contains("and(w_3, a[3], b[3]);"));
+    assert!(out.contains("assign y[3] = w_3;"));
+
+    //the first operand of a concatenation is the most significant part
+    let out = emit("{a[1], c} | 0", &mut ctx);
+    assert!(out.contains("module top(input [1:0] c, input [3:0] a, output [2:0] y);"));
+    assert!(out.contains("or(w_0, c[0], 0);"));
+    assert!(out.contains("or(w_2, a[1], 0);"));
+
+    //a single wire is zero-extended before the gates, as in Verilog
+    let out = emit("s = (t & a) | (~t & b)", &mut ctx);
+    assert!(out.contains("module top(input t, input [3:0] a, b, output [3:0] s);"));
+    assert!(out.contains("and(w_4, 0, a[1]);"));
+    assert!(out.contains("not(w_5, 0);"));
+
+    //so a mux replicates its select
+    let out = emit("s = ({t, t, t, t} & a) | (~{t, t, t, t} & b)", &mut ctx);
+    assert_eq!(out.matches("not(").count(), 1);
+    assert_eq!(out.matches("or(").count(), 4);
+
+    //shifts by a constant only rewire the bits
+    let out = emit("(a << 1) ^ (a >> 3)", &mut ctx);
+    assert!(out.contains("xor(w_0, 0, a[3]);"));
+    assert!(out.contains("xor(w_3, a[2], 0);"));
+    assert!(!out.contains("<<"));
+
+    run_command(":bus z[5:0]", &mut ctx);
+    let out = emit("z = a[2:1]", &mut ctx);
+    assert!(out.contains("output [5:0] z"));
+    assert!(out.contains("assign z[1] = a[2];"));
+    assert!(out.contains("assign z[5] = 0;"));
+
+    assert_eq!(emit("a[4]", &mut ctx), "a[4] is outside a[3:0]");
+    assert_eq!(
+        emit("a << t", &mut ctx),
+        "shl of a bus needs a constant amount"
+    );
+
+    //nothing changes for single wires
+    let out = emit("p & q", &mut ctx);
+    assert!(out.contains("module top(input p, q, output y);"));
+    assert!(out.contains("assign y = w_0;"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn verilog_assign_style() {
+    use crate::lib::context::ContextType;
+    let assign = |expr: &str| {
+        let (ans, mut ctx) = evaluate_expr_in(expr, ContextType::VerilogAssign);
+        ctx.emit_verilog(&ans)
+    };
+
+    //parentheses follow verilog's precedence, where `^` sits between `&` and `|`
+    assert!(assign("(a & b) ^ c | ~(d | e)").contains("assign y = a & b ^ c | ~(d | e);"));
+    assert!(assign("a & (b | c)").contains("assign y = a & (b | c);"));
+    assert!(assign("a ^ (b & c)").contains("assign y = a ^ b & c;"));
+    assert!(assign("(a | b) & c").contains("assign y = (a | b) & c;"));
+    assert!(assign("a & (b & c)").contains("assign y = a & b & c;"));
+    assert!(assign("nand(a, b) & ~(~a)").contains("assign y = ~(a & b) & ~~a;"));
+    assert!(assign("a << (b << c)").contains("assign y = a << (b << c);"));
+
+    //a gate read more than once keeps the wire it has in the gate-level output
+    let out = assign("((a & b) | c) ^ ((a & b) | d)");
+    assert!(out.contains("    wire w_0;\n    assign w_0 = a & b;\n"));
+    assert!(out.contains("assign y = (w_0 | c) ^ (w_0 | d);"));
+    let (ans, mut ctx) = evaluate_expr_in("((a & b) | c) ^ ((a & b) | d)", ContextType::Verilog);
+    assert!(ctx.emit_verilog(&ans).contains("and(w_0, a, b);"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn gate_level_simulation() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (ans, mut ctx) = evaluate_expr_in("0", ContextType::Verilog);
+    assert!(run_command(":sim a=1", &mut ctx)
+        .unwrap()
+        .starts_with("Nothing to simulate"));
+    ctx.emit_verilog(&ans);
+
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) | ~c", ContextType::Verilog);
+    ctx.emit_verilog(&ans);
+    assert_eq!(
+        run_command(":sim a=1 b=1 c=1", &mut ctx).unwrap(),
+        "w_0 = 1\nw_1 = 0\nw_2 = 1\ny = 1"
+    );
+    assert_eq!(
+        run_command(":sim a=0, b=1, c=1", &mut ctx).unwrap(),
+        "w_0 = 0\nw_1 = 0\nw_2 = 0\ny = 0"
+    );
+    assert_eq!(
+        run_command(":sim a=1 b=1", &mut ctx).unwrap(),
+        "No value for input c"
+    );
+    assert_eq!(
+        run_command(":sim a=2 b=1 c=0", &mut ctx).unwrap(),
+        "a is one bit wide, got 2"
+    );
+
+    let path = std::env::temp_dir().join(format!("calc_vectors_{}.csv", std::process::id()));
+    std::fs::write(&path, "c, a, b\n1, 0, 0\n0, 0, 0\n1, 1, 1\n").unwrap();
+    let out = run_command(&format!(":sim {}", path.display()), &mut ctx).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(out, "c,a,b,y\n1,0,0,0\n0,0,0,1\n1,1,1,1");
+
+    //buses take and give whole numbers
+    run_command(":bus p[3:0] q[3:0]", &mut ctx);
+    let ans = ctx.eval(&crate::parse("p ^ q").unwrap());
+    ctx.emit_verilog(&ans);
+    let out = run_command(":sim p=0b0110 q=3", &mut ctx).unwrap();
+    assert!(out.ends_with("y = 4'b0101"));
+    assert_eq!(
+        run_command(":sim p=16 q=0", &mut ctx).unwrap(),
+        "16 doesn't fit in p[3:0]"
+    );
+}
+
+#[test]
+#[cfg(test)]
+pub fn testbench_generation() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (ans, mut ctx) = evaluate_expr_in("s = (a ^ b) ^ cin", ContextType::Verilog);
+    assert_eq!(
+        run_command(":testbench", &mut ctx).unwrap(),
+        "No module emitted yet"
+    );
+    run_command(":name fa", &mut ctx);
+    ctx.emit_verilog(&ans);
+    let tb = run_command(":testbench", &mut ctx).unwrap();
+    assert!(tb.starts_with("`timescale 1ns/1ps\nmodule fa_tb;\n    reg a;\n"));
+    assert!(tb.contains("    fa dut(.a(a), .b(b), .cin(cin), .s(s));\n"));
+    assert!(tb.contains(
+        "        a = 1'b1; b = 1'b0; cin = 1'b1;\n        #1;\n        if (s !== 1'b0) \
+         $fatal(1, \"a=1'b1 b=1'b0 cin=1'b1: s is %b, expected 1'b0\", s);\n"
+    ));
+    assert!(
+        tb.ends_with("$display(\"fa: 8 vectors passed\");\n        $finish;\n    end\nendmodule")
+    );
+
+    //wide modules get random vectors, the same ones every time
+    run_command(":bus p[7:0] q[7:0]", &mut ctx);
+    run_command(":testbench vectors 20", &mut ctx);
+    let ans = ctx.eval(&crate::parse("p & q").unwrap());
+    ctx.emit_verilog(&ans);
+    let tb = run_command(":testbench", &mut ctx).unwrap();
+    assert!(tb.contains("    reg [7:0] p;\n    reg [7:0] q;\n    wire [7:0] y;\n"));
+    assert_eq!(tb.matches("$fatal").count(), 20);
+    assert!(tb.contains("20 vectors passed"));
+    assert_eq!(tb, run_command(":testbench", &mut ctx).unwrap());
+
+    let dir = std::env::temp_dir().join(format!("calc_tb_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    run_command(&format!(":testbench on {}", dir.display()), &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    let module = std::fs::read_to_string(dir.join("fa.v")).unwrap();
+    let bench = std::fs::read_to_string(dir.join("fa_tb.v")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(module, out);
+    assert!(out.contains("// testbench: "));
+    assert!(bench.contains("module fa_tb;"));
+
+    //shared gates are evaluated once, a nand-lowered xor chain shares every stage
+    let (ans, mut ctx) = evaluate_expr_in(
+        "a ^ b ^ c ^ d ^ e ^ f ^ g ^ h ^ i ^ j",
+        ContextType::VerilogNand,
+    );
+    ctx.emit_verilog(&ans);
+    let start = std::time::Instant::now();
+    let tb = run_command(":testbench", &mut ctx).unwrap();
+    assert!(start.elapsed().as_secs() < 10);
+    assert!(tb.contains("1024 vectors passed"));
+    assert!(tb.contains("expected 1'b1"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn circuit_statistics() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) | ~c", ContextType::Verilog);
+    assert_eq!(
+        run_command(":stats", &mut ctx).unwrap(),
+        "No module emitted yet"
+    );
+    ctx.emit_verilog(&ans);
+    assert_eq!(
+        run_command(":stats", &mut ctx).unwrap(),
+        "gates: 3 (and 1, not 1, or 1)\n\
+         depth: 2, a -> w_0 -> w_2 -> y\n\
+         fan-out: a 1, b 1, c 1, w_0 1, w_1 1, w_2 1 (max 1)\n\
+         transistors: 14"
+    );
+
+    let out = run_command(":stats [verilog nand] a ^ b", &mut ctx).unwrap();
+    assert!(out.starts_with("gates: 4 (nand 4)\ndepth: 3, a -> w_0 -> w_1 -> w_3 -> y\n"));
+    assert!(out.contains("a 2, b 2, w_0 2"));
+    assert!(out.ends_with("transistors: 16"));
+
+    assert_eq!(
+        run_command(":stats compare (a ^ b) ^ c", &mut ctx).unwrap(),
+        "context      gates depth transistors\n\
+         verilog          2     2          24\n\
+         verilog nand     8     6          32\n\
+         verilog nor     10     8          40"
+    );
+
+    let out = run_command(":stats a << b", &mut ctx).unwrap();
+    assert!(out.ends_with("transistors: 0, not counting 1 shift gates"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn chain_balancing() {
+    use crate::lib::{command::run_command, context::ContextType};
+    use std::collections::HashMap;
+    let (ans, mut ctx) = evaluate_expr_in("a & b & c & d & e", ContextType::Verilog);
+    ctx.emit_verilog(&ans);
+    let chain = ctx.netlist.clone().unwrap();
+    assert_eq!(chain.depth(), 4);
+
+    assert_eq!(run_command(":balance on", &mut ctx).unwrap(), "");
+    let out = ctx.emit_verilog(&ans);
+    assert!(
+        out.contains("depth: 4 before balancing, 3 after, 1 saved"),
+        "{}",
+        out
+    );
+    let balanced = ctx.netlist.clone().unwrap();
+    assert_eq!(balanced.gates.len(), 4);
+    for m in 0..32 {
+        let inputs: HashMap<String, bool> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.to_string(), m & (1 << i) != 0))
+            .collect();
+        assert_eq!(
+            chain.output_values(&inputs),
+            balanced.output_values(&inputs)
+        );
+    }
+
+    //shallow operands are paired first, the deep one joins last
+    let (ans, mut ctx) = evaluate_expr_in("(p ^ q ^ r ^ s) | a | b | c", ContextType::Verilog);
+    run_command(":balance on", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(
+        out.contains("depth: 6 before balancing, 3 after, 3 saved"),
+        "{}",
+        out
+    );
+
+    //a gate read twice is shared, not folded into a chain
+    let (ans, mut ctx) =
+        evaluate_expr_in("t(a, b, c) = (a & b) & c & (a & b)", ContextType::Verilog);
+    run_command(":balance on", &mut ctx);
+    ctx.emit_verilog(&ans);
+    let netlist = ctx.netlist.clone().unwrap();
+    assert_eq!(netlist.gates.len(), 3);
+    assert_eq!(netlist.depth(), 3);
+}
+
+#[test]
+#[cfg(test)]
+pub fn cell_library_mapping() {
+    use crate::lib::{
+        cells::CellLibrary,
+        command::run_command,
+        context::{ContextManager, ContextType},
+    };
+    use std::collections::HashMap;
+    let library = "# name area delay function\n\
+                   INV    1 1   y = ~a\n\
+                   NAND2  2 1   y = ~(a & b)\n\
+                   NOR2   2 1.2 y = ~(a | b)\n\
+                   AOI21  3 1.4 y = ~((a & b) | c)\n\
+                   XOR2   5 2   y = a ^ b\n";
+    let path = std::env::temp_dir().join(format!("calc_cells_{}.lib", std::process::id()));
+    std::fs::write(&path, library).unwrap();
+
+    let mut ctx = ContextManager::new();
+    ctx.push_stack_frame(ContextType::Calculate);
+    let eval = |line: &str, ctx: &mut ContextManager| {
+        crate::eval(
+            &mut format!("{}\n", line),
+            &mut String::new(),
+            &mut Vec::new(),
+            ctx,
+        )
+    };
+    eval(
+        &format!("context verilog cells {}", path.display()),
+        &mut ctx,
+    );
+    //the library is read when the context is entered, a failing assert below can't leak it
+    std::fs::remove_file(&path).unwrap();
+    let ans = eval("(a & b) | c", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.contains("// cells: 2 (AOI21 1, INV 1), area 4, delay 2.4 (minimizing area)"));
+    assert!(out.contains("AOI21 u_0(.a(a), .b(b), .c(c), .y(w_0));"));
+    assert!(out.contains("INV u_1(.a(w_0), .y(w_1));"));
+    assert!(run_command(":stats", &mut ctx)
+        .unwrap()
+        .ends_with("area: 4"));
+
+    run_command(":cells delay", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.contains("// cells: 3 (INV 1, NAND2 2), area 5, delay 2 (minimizing delay)"));
+    assert!(run_command(":testbench", &mut ctx)
+        .unwrap()
+        .contains("8 vectors passed"));
+
+    //the mapped netlist computes the same outputs
+    let expr = "(a ^ b) & ~(c | d) | a & d";
+    let (ans, mut reference) = evaluate_expr_in(expr, ContextType::Verilog);
+    reference.emit_verilog(&ans);
+    let reference = reference.netlist.unwrap();
+    let ans = eval(expr, &mut ctx);
+    ctx.emit_verilog(&ans);
+    let mapped = ctx.netlist.clone().unwrap();
+    assert!(mapped.gates.iter().all(|f| mapped.cell(&f.kind).is_some()));
+    for m in 0..16 {
+        let inputs: HashMap<String, bool> = ["a", "b", "c", "d"]
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.to_string(), m & (1 << i) != 0))
+            .collect();
+        assert_eq!(
+            reference.output_values(&inputs),
+            mapped.output_values(&inputs)
+        );
+    }
+
+    ctx.verilog.cells = Some(CellLibrary::parse("AND2 3 1.5 y = a & b").unwrap());
+    let ans = eval("~a", &mut ctx);
+    assert!(ctx
+        .emit_verilog(&ans)
+        .starts_with("The cell library can't cover the circuit"));
+    assert_eq!(
+        CellLibrary::parse("INV 1 fast y = ~a").unwrap_err(),
+        "Line 1 of the cell library: Expected a number for INV, got fast"
+    );
+}
+
+#[test]
+#[cfg(test)]
+pub fn netlist_export_formats() {
+    use crate::lib::{
+        cells::CellLibrary,
+        command::run_command,
+        context::ContextType,
+        export::{BlifExporter, Exporter},
+    };
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) | ~c", ContextType::Verilog);
+    assert_eq!(run_command(":export", &mut ctx).unwrap(), "verilog");
+    assert!(ctx
+        .emit_verilog(&ans)
+        .contains("module top(input a, b, c, output y);"));
+
+    run_command(":export blif", &mut ctx);
+    assert_eq!(
+        ctx.emit_verilog(&ans),
+        "# gates: 3 before hash-consing, 3 after\n\
+         .model top\n\
+         .inputs a b c\n\
+         .outputs y\n\
+         .names a b w_0\n\
+         11 1\n\
+         .names c w_1\n\
+         0 1\n\
+         .names w_0 w_1 w_2\n\
+         01 1\n\
+         10 1\n\
+         11 1\n\
+         .names w_2 y\n\
+         1 1\n\
+         .end"
+    );
+
+    run_command(":export json", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.starts_with("{\n  \"module\": \"top\",\n"));
+    assert!(out.contains("{\"id\": \"a\", \"type\": \"input\"}"));
+    assert!(out.contains("{\"id\": \"w_1\", \"type\": \"not\"}"));
+    assert!(out.contains("{\"from\": \"w_2\", \"to\": \"y\"}"));
+
+    run_command(":export dot", &mut ctx);
+    let out = ctx.emit_verilog(&ans);
+    assert!(out.contains("digraph \"top\" {\n    rankdir=LR;\n"));
+    assert!(out.contains("    \"w_0\" [label=\"and\\nw_0\"];\n"));
+    assert!(out.contains("    \"c\" -> \"w_1\";\n"));
+    assert!(out.ends_with("    \"w_2\" -> \"y\";\n}"));
+
+    let path = std::env::temp_dir().join(format!("calc_export_{}.blif", std::process::id()));
+    let path = path.to_str().unwrap();
+    assert_eq!(
+        run_command(&format!(":export blif {}", path), &mut ctx).unwrap(),
+        format!("wrote {}", path)
+    );
+    assert!(std::fs::read_to_string(path)
+        .unwrap()
+        .contains(".names a b w_0\n11 1\n"));
+    assert_eq!(run_command(":export", &mut ctx).unwrap(), "dot");
+    assert!(run_command(":export edif", &mut ctx)
+        .unwrap()
+        .starts_with("Unknown export format"));
+
+    //constants are folded into the tables, cells become .gate lines
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) | 1", ContextType::Verilog);
+    ctx.emit_verilog(&ans);
+    let out = BlifExporter.export(ctx.netlist.as_ref().unwrap()).unwrap();
+    assert!(out.contains(".names w_0 w_1\n0 1\n1 1\n"));
+    let (ans, mut ctx) = evaluate_expr_in("(a & b) | 1", ContextType::VerilogCells);
+    ctx.verilog.cells = Some(CellLibrary::parse("INV 1 1 y = ~a\nNAND2 2 1 y = ~(a & b)").unwrap());
+    ctx.emit_verilog(&ans);
+    let out = BlifExporter.export(ctx.netlist.as_ref().unwrap()).unwrap();
+    assert!(out.contains(".gate NAND2 a=a b=b y=w_0\n.gate INV a=const_1 y=w_1\n"));
+    assert!(out.ends_with(".names const_1\n1\n.end"));
+}
+
+#[test]
+#[cfg(test)]
+pub fn expression_trees_as_dot() {
+    use crate::lib::{
+        command::run_command,
+        context::ContextType,
+        export::tree_to_dot,
+        node::{CalcFunctionData, CalcNode},
+    };
+    let (_, mut ctx) = evaluate_expr_in("", ContextType::Calculate);
+    assert_eq!(
+        run_command(":dot 1 + 2 * 3", &mut ctx).unwrap(),
+        "digraph expr {\n    \
+             ordering=out;\n    \
+             n0 [label=\"+\", shape=ellipse];\n    \
+             n1 [label=\"1\", shape=plaintext];\n    \
+             n0 -> n1;\n    \
+             n2 [label=\"*\", shape=ellipse];\n    \
+             n3 [label=\"2\", shape=plaintext];\n    \
+             n2 -> n3;\n    \
+             n4 [label=\"3\", shape=plaintext];\n    \
+             n2 -> n4;\n    \
+             n0 -> n2;\n\
+         }"
+    );
+    //parsed functions all have id 0, they aren't merged into one node
+    let parsed = tree_to_dot(&crate::parse("1 + 2 * 3").unwrap(), true);
+    assert_eq!(parsed.matches("shape=ellipse").count(), 2);
+    assert!(parsed.contains("n0 -> n2;"));
+    assert!(run_command(":dot eval 1 + 2 * 3", &mut ctx)
+        .unwrap()
+        .contains("n0 [label=\"7\", shape=plaintext];"));
+
+    let (_, mut ctx) = evaluate_expr_in("", ContextType::Verilog);
+    let out = run_command(":dot eval a & ~b", &mut ctx).unwrap();
+    assert!(out.contains("n0 [label=\"and &\\n#1\", shape=ellipse];"));
+    assert!(out.contains("n2 [label=\"not ~\\n#0\", shape=ellipse];"));
+    assert!(out.contains("n3 [label=\"b\", shape=box];"));
+
+    //a gate reached twice is drawn once when shared
+    let gate = CalcNode::Function(CalcFunctionData {
+        params: vec![CalcNode::Text("a".to_string())],
+        id: 7,
+        ..CalcFunctionData::new("not")
+    });
+    let root = CalcNode::Function(CalcFunctionData {
+        params: vec![gate.clone(), gate],
+        id: 8,
+        ..CalcFunctionData::new("xor")
+    });
+    let shared = tree_to_dot(&root, true);
+    assert_eq!(shared.matches("label=\"not\\n#7\"").count(), 1);
+    assert_eq!(shared.matches("n0 -> n1;").count(), 2);
+    assert_eq!(
+        tree_to_dot(&root, false)
+            .matches("label=\"not\\n#7\"")
+            .count(),
+        2
+    );
+}
+
+#[test]
+#[cfg(test)]
+pub fn wires_are_named_and_numbered_in_order() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let module = |extra: &str| {
+        let (_, mut ctx) =
+            evaluate_expr_in("carry(x, y, z) = x & y | z & (x ^ y)", ContextType::Verilog);
+        run_command(extra, &mut ctx);
+        for line in ["s = a ^ b ^ c", "co = carry(a, b, c) & e"] {
+            crate::eval(
+                &mut line.to_string(),
+                &mut String::new(),
+                &mut Vec::new(),
+                &mut ctx,
+            );
+        }
+        run_command(":module adder s co", &mut ctx).unwrap()
+    };
+    let out = module(":balance off");
+    assert_eq!(
+        out,
+        "// gates: 7 before hash-consing, 7 after\n\
+         module adder(input a, b, c, e, output s, co);\n    \
+             wire w_0, w_s_0, w_1, w_2, w_3, w_carry_0, w_co_0;\n    \
+             xor(w_0, b, c);\n    \
+             xor(w_s_0, a, w_0);\n    \
+             and(w_1, a, b);\n    \
+             xor(w_2, a, b);\n    \
+             and(w_3, c, w_2);\n    \
+             or(w_carry_0, w_1, w_3);\n    \
+             and(w_co_0, w_carry_0, e);\n    \
+             assign s = w_s_0;\n    \
+             assign co = w_co_0;\n\
+         endmodule"
+    );
+    assert_eq!(out, module(":balance off"));
+
+    //the wires are renumbered after the passes removing and adding gates
+    let out = module(":balance on");
+    assert!(out.contains("wire w_0, w_s_0, w_1, w_2, w_3, w_carry_0, w_co_0;"));
+
+    let mut ctx = evaluate_expr_in("double(x) = x * 2", ContextType::Calculate).1;
+    let ans = crate::eval(
+        &mut "double(3) + 1".to_string(),
+        &mut String::new(),
+        &mut Vec::new(),
+        &mut ctx,
+    );
+    assert_eq!(ans, crate::lib::node::CalcNode::SingleValue(7.0));
+}
+
+#[test]
+#[cfg(test)]
+pub fn registers_emit_always_blocks() {
+    use crate::lib::{
+        command::run_command,
+        context::ContextType,
+        netlist::Netlist,
+        node::{CalcFunctionData, CalcNode},
+        seq::Register,
+    };
+    let (_, mut ctx) = evaluate_expr_in("", ContextType::Verilog);
+    run_command(":bus count[1:0]", &mut ctx);
+    crate::eval(
+        &mut "reg count <= count ^ {count[0], 1} on clk reset rst = 2\n".to_string(),
+        &mut String::new(),
+        &mut Vec::new(),
+        &mut ctx,
+    );
+    assert_eq!(
+        run_command(":module counter count", &mut ctx).unwrap(),
+        "// gates: 2 before hash-consing, 2 after\n\
+         module counter(input clk, rst, output reg [1:0] count);\n    \
+             wire w_0, w_1;\n    \
+             xor(w_0, count[0], 1);\n    \
+             xor(w_1, count[1], count[0]);\n    \
+             always @(posedge clk) begin\n        \
+                 if (rst) begin\n            \
+                     count[0] <= 1'b0;\n            \
+                     count[1] <= 1'b1;\n        \
+                 end else begin\n            \
+                     count[0] <= w_0;\n            \
+                     count[1] <= w_1;\n        \
+                 end\n    \
+             end\n\
+         endmodule"
+    );
+    //registers hold their reset value until clocked
+    assert_eq!(
+        run_command(":sim clk=0 rst=0", &mut ctx).unwrap(),
+        "w_0 = 1\nw_1 = 1\ncount = 2'b10"
+    );
+    run_command(":export blif", &mut ctx);
+    let out = run_command(":module counter count", &mut ctx).unwrap();
+    assert!(out.contains(".names rst w_1 count[1]_next\n1- 1\n01 1\n"));
+    assert!(out.contains(".latch count[1]_next count[1] re clk 1\n"));
+    assert!(!out.contains(".names count[0] count[0]"));
+    run_command(":export verilog", &mut ctx);
+
+    //prev is a register of its own on the clock of the declared registers
+    crate::eval(
+        &mut "rise = a & ~prev(a)".to_string(),
+        &mut String::new(),
+        &mut Vec::new(),
+        &mut ctx,
+    );
+    crate::eval(
+        &mut "reg q <= ~q on clk".to_string(),
+        &mut String::new(),
+        &mut Vec::new(),
+        &mut ctx,
+    );
+    let out = run_command(":module edge rise q", &mut ctx).unwrap();
+    assert!(
+        out.contains("module edge(input a, clk, output rise, output reg q);\n    reg prev_0;\n")
+    );
+    assert!(out.contains(
+        "    always @(posedge clk) begin\n        prev_0 <= a;\n        q <= w_1;\n    end\n"
+    ));
+    assert!(!out.contains("assign q"));
+    run_command(":testbench vectors 3", &mut ctx);
+    assert!(run_command(":testbench", &mut ctx).unwrap().contains(
+        "        dut.prev_0 = 1'b0;\n        \
+         dut.q = 1'b0;\n        \
+         a = 1'b1;\n        \
+         #1;\n        \
+         if (rise !== 1'b1) $fatal(1, \"cycle 0 a=1'b1: rise is %b, expected 1'b1\", rise);\n        \
+         if (q !== 1'b0) $fatal(1, \"cycle 0 a=1'b1: q is %b, expected 1'b0\", q);\n        \
+         clk = 1'b1; #1; clk = 1'b0;\n        \
+         a = 1'b0;\n        \
+         #1;\n        \
+         if (rise !== 1'b0) $fatal(1, \"cycle 1 a=1'b0: rise is %b, expected 1'b0\", rise);\n        \
+         if (q !== 1'b1) $fatal(1, \"cycle 1 a=1'b0: q is %b, expected 1'b1\", q);\n"
+    ));
+
+    //a prev reached twice is one register
+    let prev = CalcNode::Function(CalcFunctionData {
+        params: vec![CalcNode::Text("a".to_string())],
+        id: 4,
+        ..CalcFunctionData::new("prev")
+    });
+    let mut netlist = Netlist::new("twice");
+    netlist.add_output(
+        "y",
+        &CalcNode::Function(CalcFunctionData {
+            params: vec![prev.clone(), prev],
+            id: 5,
+            ..CalcFunctionData::new("xor")
+        }),
+    );
+    assert_eq!(netlist.flops.len(), 1);
+
+    assert!(Register::try_from("q <= d").is_err());
+    assert!(Register::try_from("q <= d on clk reset rst = x").is_err());
+    let r = Register::try_from("q <= d on clk reset rst = 0x3").unwrap();
+    assert_eq!(
+        (r.clock.as_str(), r.reset),
+        ("clk", Some(("rst".to_string(), 3)))
+    );
+}
+
+#[test]
+#[cfg(test)]
+pub fn state_machines_emit_modules_and_diagrams() {
+    use crate::lib::{command::run_command, context::ContextType};
+    let (_, mut ctx) = evaluate_expr_in("", ContextType::Verilog);
+    let describe = |lines: &[&str], ctx: &mut crate::lib::context::ContextManager| {
+        for line in lines {
+            crate::eval(
+                &mut line.to_string(),
+                &mut String::new(),
+                &mut Vec::new(),
+                ctx,
+            );
+        }
+    };
+    assert_eq!(
+        run_command(":fsm", &mut ctx).unwrap(),
+        "No fsm declared, start one with fsm <name> on <clock> reset <signal>"
+    );
+    describe(
+        &[
+            "fsm det on clk reset rst",
+            "state low",
+            "state high",
+            "trans low -> high if x",
+            "trans high -> low if ~x / fall = 1",
+        ],
+        &mut ctx,
+    );
+    //lines starting with a keyword but not shaped like a declaration are expressions
+    for expr in ["state & go", "trans | x", "fsm ^ on", "reg & q"] {
+        let ans = crate::eval(
+            &mut expr.to_string(),
+            &mut String::new(),
+            &mut Vec::new(),
+            &mut ctx,
+        );
+        assert!(
+            matches!(ans, crate::lib::node::CalcNode::Function(_)),
+            "{}",
+            expr
+        );
+    }
+    assert_eq!(
+        run_command(":fsm", &mut ctx).unwrap(),
+        "// states: low 0, high 1 (binary)\n\
+         // gates: 6 before hash-consing, 6 after\n\
+         module det(input x, clk, rst, output fall);\n    \
+             reg [0:0] state;\n    \
+             wire w_0, w_1, w_2, w_3, w_4, w_5;\n    \
+             not(w_0, x);\n    \
+             and(w_1, state[0], w_0);\n    \
+             not(w_2, state[0]);\n    \
+             and(w_3, w_2, x);\n    \
+             and(w_4, state[0], x);\n    \
+             or(w_5, w_3, w_4);\n    \
+             assign fall = w_1;\n    \
+             always @(posedge clk) begin\n        \
+                 if (rst) begin\n            \
+                     state[0] <= 1'b0;\n        \
+                 end else begin\n            \
+                     state[0] <= w_5;\n        \
+                 end\n    \
+             end\n\
+         endmodule"
+    );
+    //the machine is reset, then checked cycle by cycle against its transitions
+    run_command(":testbench vectors 4", &mut ctx);
+    let tb = run_command(":testbench", &mut ctx).unwrap();
+    assert!(tb.contains(
+        "        clk = 1'b0;\n        \
+         dut.state[0] = 1'b0;\n        \
+         rst = 1'b1; x = 1'b0;\n        \
+         #1; clk = 1'b1; #1; clk = 1'b0;\n        \
+         rst = 1'b0;\n"
+    ));
+    assert_eq!(tb.matches("clk = 1'b1; #1; clk = 1'b0;").count(), 5);
+    assert!(tb.contains("det: 4 vectors passed"));
+    assert_eq!(
+        run_command(":sim x=0 clk=0 rst=0", &mut ctx).unwrap(),
+        "w_0 = 1\nw_1 = 0\nw_2 = 1\nw_3 = 0\nw_4 = 0\nw_5 = 0\nfall = 0"
+    );
+
+    describe(
+        &[
+            "fsm light on clk reset rst",
+            "state red stop = 1",
+            "state green",
+            "state yellow stop = 1",
+            "trans red -> green if go",
+            "trans green -> yellow if ~go",
+            "trans yellow -> red",
+        ],
+        &mut ctx,
+    );
+    assert_eq!(
+        run_command(":fsm dot", &mut ctx).unwrap(),
+        "digraph \"light\" {\n    \
+             rankdir=LR;\n    \
+             \"red\" [shape=doublecircle, label=\"red\\n00\\nstop\"];\n    \
+             \"green\" [shape=circle, label=\"green\\n01\"];\n    \
+             \"yellow\" [shape=circle, label=\"yellow\\n10\\nstop\"];\n    \
+             \"red\" -> \"green\" [label=\"go\"];\n    \
+             \"green\" -> \"yellow\" [label=\"~go\"];\n    \
+             \"yellow\" -> \"red\" [label=\"\"];\n\
+         }"
+    );
+    run_command(":fsm onehot", &mut ctx);
+    let out = run_command(":fsm", &mut ctx).unwrap();
+    assert!(out.starts_with("// states: red 001, green 010, yellow 100 (onehot)\n"));
+    assert!(out.contains("    reg [2:0] state;\n"));
+    assert!(out.contains("            state[0] <= 1'b1;\n            state[1] <= 1'b0;\n"));
+    run_command(":fsm gray", &mut ctx);
+    assert!(run_command(":fsm dot", &mut ctx)
+        .unwrap()
+        .contains("label=\"yellow\\n11\\nstop\""));
+    assert_eq!(
+        run_command(":fsm ternary", &mut ctx),
+        Some("Expected binary, onehot or gray, got ternary".to_string())
+    );
+
+    describe(&["trans red -> blue"], &mut ctx);
+    assert_eq!(
+        run_command(":fsm", &mut ctx),
+        Some("No state named blue in light".to_string())
+    );
+    describe(
+        &[
+            "fsm m on clk reset rst",
+            "state a y = 1",
+            "trans a -> a / y = 0",
+        ],
+        &mut ctx,
+    );
+    assert_eq!(
+        run_command(":fsm", &mut ctx),
+        Some("y is set by both states and transitions".to_string())
+    );
+}